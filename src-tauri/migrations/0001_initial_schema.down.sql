@@ -0,0 +1,6 @@
+DROP TABLE IF EXISTS transaction_header;
+DROP TABLE IF EXISTS enrollments;
+DROP TABLE IF EXISTS subjects;
+DROP TABLE IF EXISTS shifts;
+DROP TABLE IF EXISTS rooms;
+DROP TABLE IF EXISTS users;
@@ -0,0 +1,49 @@
+-- Tables previously created by the create_*_table_if_not_exists functions.
+-- IF NOT EXISTS lets databases that predate migrations adopt this version as-is.
+
+CREATE TABLE IF NOT EXISTS users (
+    bn_number VARCHAR(255) NOT NULL,
+    nim VARCHAR(255) PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    major VARCHAR(255),
+    role VARCHAR(255),
+    initial VARCHAR(255),
+    password VARCHAR(255)
+);
+
+CREATE TABLE IF NOT EXISTS rooms (
+    room_number VARCHAR(255) PRIMARY KEY,
+    room_capacity INT NOT NULL,
+    campus VARCHAR(255) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS shifts (
+    shift_code VARCHAR(255) PRIMARY KEY,
+    start_time TIME NOT NULL,
+    end_time TIME NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS subjects (
+    subject_code VARCHAR(255) PRIMARY KEY,
+    subject_name VARCHAR(255) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS enrollments (
+    subject_code VARCHAR(255) NOT NULL,
+    nim VARCHAR(255) NOT NULL,
+    class_code VARCHAR(255) PRIMARY KEY,
+    FOREIGN KEY (subject_code) REFERENCES subjects(subject_code),
+    FOREIGN KEY (nim) REFERENCES users(nim)
+);
+
+CREATE TABLE IF NOT EXISTS transaction_header (
+    transaction_code VARCHAR(255) PRIMARY KEY,
+    subject_code VARCHAR(255) NOT NULL,
+    shift_code VARCHAR(255) NOT NULL,
+    room_number VARCHAR(255) NOT NULL,
+    date DATE NOT NULL,
+    proctor VARCHAR(255),
+    FOREIGN KEY (subject_code) REFERENCES subjects(subject_code),
+    FOREIGN KEY (shift_code) REFERENCES shifts(shift_code),
+    FOREIGN KEY (room_number) REFERENCES rooms(room_number)
+);
@@ -0,0 +1,4 @@
+-- Fails if a class already holds more than one student; clear those rows first.
+ALTER TABLE enrollments
+    DROP PRIMARY KEY,
+    ADD PRIMARY KEY (class_code);
@@ -0,0 +1,4 @@
+-- class_code alone kept only one student per class; key on the full enrollment instead.
+ALTER TABLE enrollments
+    DROP PRIMARY KEY,
+    ADD PRIMARY KEY (subject_code, class_code, nim);
@@ -0,0 +1,2 @@
+ALTER TABLE transaction_header
+    DROP INDEX uq_transaction_room_slot;
@@ -0,0 +1,5 @@
+-- A room can hold only one exam per date and shift.
+-- migrations::migrate lists existing double bookings and stops before this runs
+-- until they are resolved.
+ALTER TABLE transaction_header
+    ADD CONSTRAINT uq_transaction_room_slot UNIQUE (date, shift_code, room_number);
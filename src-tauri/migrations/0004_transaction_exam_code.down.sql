@@ -0,0 +1,3 @@
+ALTER TABLE transaction_header
+    DROP INDEX idx_transaction_exam_code,
+    DROP COLUMN exam_code;
@@ -0,0 +1,6 @@
+-- Groups the transaction_header rows of an exam split over several rooms.
+ALTER TABLE transaction_header
+    ADD COLUMN exam_code VARCHAR(255) NULL AFTER transaction_code,
+    ADD INDEX idx_transaction_exam_code (exam_code);
+
+UPDATE transaction_header SET exam_code = transaction_code WHERE exam_code IS NULL;
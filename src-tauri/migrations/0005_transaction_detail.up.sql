@@ -0,0 +1,10 @@
+-- Students seated in each transaction_header row, with the class they sit it for.
+CREATE TABLE IF NOT EXISTS transaction_detail (
+    transaction_code VARCHAR(255) NOT NULL,
+    class_code VARCHAR(255) NOT NULL,
+    nim VARCHAR(255) NOT NULL,
+    PRIMARY KEY (transaction_code, nim),
+    INDEX idx_transaction_detail_nim (nim),
+    FOREIGN KEY (transaction_code) REFERENCES transaction_header(transaction_code) ON DELETE CASCADE,
+    FOREIGN KEY (nim) REFERENCES users(nim)
+);
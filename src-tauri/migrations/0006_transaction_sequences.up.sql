@@ -0,0 +1,5 @@
+-- Last number handed out per transaction code period (see codes::CodeFormat).
+CREATE TABLE IF NOT EXISTS transaction_sequences (
+    period VARCHAR(255) PRIMARY KEY,
+    last_value BIGINT UNSIGNED NOT NULL
+);
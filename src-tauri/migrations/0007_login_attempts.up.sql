@@ -0,0 +1,12 @@
+-- Every password check made by `login`, kept for throttling and auditing.
+-- `cleared` is set when an admin lifts a lockout, so older failures stop counting.
+CREATE TABLE IF NOT EXISTS login_attempts (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    username VARCHAR(255) NOT NULL,
+    machine VARCHAR(255) NOT NULL,
+    succeeded BOOLEAN NOT NULL,
+    cleared BOOLEAN NOT NULL DEFAULT FALSE,
+    attempted_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX idx_login_attempts_username (username, attempted_at),
+    INDEX idx_login_attempts_machine (machine, attempted_at)
+);
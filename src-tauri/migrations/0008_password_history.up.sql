@@ -0,0 +1,8 @@
+-- Hashes of every password a user has set, newest last, so change_password can refuse reuse.
+CREATE TABLE IF NOT EXISTS password_history (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    bn_number VARCHAR(255) NOT NULL,
+    password_hash VARCHAR(255) NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX idx_password_history_bn_number (bn_number, id)
+);
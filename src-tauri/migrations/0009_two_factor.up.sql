@@ -0,0 +1,20 @@
+-- TOTP secrets (hex) of users who started enrolling. totp_enabled is set once
+-- a first code has been confirmed; totp_last_step stops a code being reused.
+ALTER TABLE users
+    ADD COLUMN totp_secret VARCHAR(64) NULL,
+    ADD COLUMN totp_enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN totp_last_step BIGINT UNSIGNED NULL;
+
+-- Single-use codes for users who lost their authenticator, stored as SHA-256 hashes.
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    bn_number VARCHAR(255) NOT NULL,
+    code_hash CHAR(64) NOT NULL,
+    used_at DATETIME NULL,
+    INDEX idx_recovery_codes_bn_number (bn_number)
+);
+
+-- Roles whose users must enroll in two-factor authentication.
+CREATE TABLE IF NOT EXISTS two_factor_roles (
+    role VARCHAR(255) PRIMARY KEY
+);
@@ -0,0 +1,25 @@
+-- One-time codes an admin issues so a user who forgot their password can set
+-- a new one. Only the SHA-256 hash is kept; a code works once, until expires_at.
+CREATE TABLE IF NOT EXISTS password_resets (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    bn_number VARCHAR(255) NOT NULL,
+    code_hash CHAR(64) NOT NULL,
+    issued_by VARCHAR(255) NOT NULL,
+    issued_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at DATETIME NOT NULL,
+    used_at DATETIME NULL,
+    INDEX idx_password_resets_bn_number (bn_number, expires_at)
+);
+
+-- Security-relevant actions, newest last. `details` holds JSON.
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    actor VARCHAR(255) NOT NULL,
+    action VARCHAR(64) NOT NULL,
+    target VARCHAR(255) NULL,
+    machine VARCHAR(255) NOT NULL,
+    details TEXT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX idx_audit_log_target (target, id),
+    INDEX idx_audit_log_action (action, id)
+);
@@ -0,0 +1,7 @@
+-- A suspended account cannot log in and its sessions stop working.
+-- active = FALSE with suspended_until NULL lasts until an admin reinstates the
+-- account; with a date it ends by itself. The sync never writes these columns.
+ALTER TABLE users
+    ADD COLUMN active BOOLEAN NOT NULL DEFAULT TRUE,
+    ADD COLUMN suspended_until DATETIME NULL,
+    ADD COLUMN suspension_reason VARCHAR(255) NULL;
@@ -0,0 +1,13 @@
+-- One row per sync with the GraphQL API. `report` holds the per-table counts
+-- as JSON once the run finished; `error` says why a failed run failed.
+CREATE TABLE IF NOT EXISTS sync_runs (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    started_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    finished_at DATETIME NULL,
+    status VARCHAR(16) NOT NULL,
+    inserted INT UNSIGNED NOT NULL DEFAULT 0,
+    updated INT UNSIGNED NOT NULL DEFAULT 0,
+    deleted INT UNSIGNED NOT NULL DEFAULT 0,
+    report TEXT NULL,
+    error TEXT NULL
+);
@@ -0,0 +1,12 @@
+-- Synced columns changed in this app, so sync can apply the conflict policy
+-- instead of overwriting them. base_value is what upstream had before the
+-- first local edit; row_key is the row's key columns as a JSON array.
+CREATE TABLE IF NOT EXISTS local_edits (
+    table_name VARCHAR(64) NOT NULL,
+    row_key VARCHAR(255) NOT NULL,
+    column_name VARCHAR(64) NOT NULL,
+    base_value VARCHAR(255) NULL,
+    edited_by VARCHAR(255) NOT NULL,
+    edited_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (table_name, row_key, column_name)
+);
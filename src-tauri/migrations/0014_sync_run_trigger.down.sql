@@ -0,0 +1,2 @@
+ALTER TABLE sync_runs
+    DROP COLUMN triggered_by;
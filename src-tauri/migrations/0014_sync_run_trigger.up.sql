@@ -0,0 +1,4 @@
+-- What started each sync: "startup", "schedule" or the bn_number of the admin
+-- who ran trigger_sync.
+ALTER TABLE sync_runs
+    ADD COLUMN triggered_by VARCHAR(255) NOT NULL DEFAULT 'startup';
@@ -0,0 +1,4 @@
+-- Shifts already used by exams stay.
+DELETE FROM shifts
+WHERE shift_code IN ('1', '2', '3', '4', '5', '6', '7')
+    AND shift_code NOT IN (SELECT shift_code FROM transaction_header);
@@ -0,0 +1,9 @@
+-- The fixed exam shifts, seeded here instead of after every sync.
+INSERT IGNORE INTO shifts (shift_code, start_time, end_time) VALUES
+    ('1', '07:00:00', '09:00:00'),
+    ('2', '09:00:00', '11:00:00'),
+    ('3', '11:00:00', '13:00:00'),
+    ('4', '13:00:00', '15:00:00'),
+    ('5', '15:00:00', '17:00:00'),
+    ('6', '17:00:00', '19:00:00'),
+    ('7', '19:00:00', '21:00:00');
@@ -0,0 +1,412 @@
+use std::collections::HashSet;
+
+use mysql::prelude::*;
+use mysql::Transaction;
+use serde::Serialize;
+
+use crate::codes::{CodeFormat, SequenceSource};
+use crate::error::{is_duplicate_key, AppError, AppResult, ErrorCode};
+
+const MAX_CODE_ATTEMPTS: usize = 5;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StudentClash {
+    pub nim: String,
+    pub subject_code: String,
+    pub transaction_code: String,
+}
+
+/// One `transaction_header` row of an exam: a room and the classes seated in it.
+#[derive(Clone, Debug, Serialize)]
+pub struct RoomAllocation {
+    pub transaction_code: String,
+    pub room_number: String,
+    pub class_codes: Vec<String>,
+    pub student_count: u32,
+    pub room_capacity: u32,
+}
+
+pub struct ExamRequest<'a> {
+    pub subject_code: &'a str,
+    pub class_codes: &'a [String],
+    pub date: &'a str,
+    pub shift_code: &'a str,
+}
+
+pub fn placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
+
+fn values(leading: &[&str], list: &[String]) -> Vec<mysql::Value> {
+    leading
+        .iter()
+        .map(|value| (*value).into())
+        .chain(list.iter().map(|value| value.as_str().into()))
+        .collect()
+}
+
+// Number of distinct students in each chosen class, in the order the classes were given
+fn count_students(transaction: &mut Transaction, request: &ExamRequest) -> AppResult<Vec<(String, u32)>> {
+    let query = format!(
+        "SELECT class_code, COUNT(DISTINCT nim) FROM enrollments WHERE subject_code = ? AND class_code IN ({}) GROUP BY class_code",
+        placeholders(request.class_codes.len())
+    );
+    let counts: Vec<(String, u32)> = transaction
+        .exec(query, values(&[request.subject_code], request.class_codes))
+        .map_err(|e| AppError::database("Failed to count enrolled students", e))?;
+
+    request
+        .class_codes
+        .iter()
+        .map(|class_code| {
+            counts
+                .iter()
+                .find(|(code, _)| code == class_code)
+                .cloned()
+                .ok_or_else(|| {
+                    AppError::validation(format!("Class {} has no students enrolled in {}", class_code, request.subject_code))
+                })
+        })
+        .collect()
+}
+
+fn room_capacities(transaction: &mut Transaction, room_numbers: &[String]) -> AppResult<Vec<(String, u32)>> {
+    let query = format!(
+        "SELECT room_number, room_capacity FROM rooms WHERE room_number IN ({})",
+        placeholders(room_numbers.len())
+    );
+    let rooms: Vec<(String, u32)> = transaction
+        .exec(query, values(&[], room_numbers))
+        .map_err(|e| AppError::database("Failed to query rooms", e))?;
+
+    room_numbers
+        .iter()
+        .map(|room_number| {
+            rooms
+                .iter()
+                .find(|(number, _)| number == room_number)
+                .cloned()
+                .ok_or_else(|| AppError::not_found(format!("Room {} does not exist", room_number)))
+        })
+        .collect()
+}
+
+/// The first entry listed more than once, if any.
+fn first_duplicate<'a>(mut items: impl Iterator<Item = &'a String>) -> Option<&'a String> {
+    let mut seen = HashSet::new();
+    items.find(|item| !seen.insert(*item))
+}
+
+/// Seats whole classes into rooms, largest class first, each into the first room
+/// with enough space left. Rooms that end up empty are left out of the plan.
+pub fn plan_rooms(classes: &[(String, u32)], rooms: &[(String, u32)]) -> AppResult<Vec<RoomAllocation>> {
+    // A class listed twice would be seated twice
+    if let Some(class_code) = first_duplicate(classes.iter().map(|(code, _)| code)) {
+        return Err(AppError::validation(format!("Class {} is listed more than once", class_code)));
+    }
+
+    let total_students: u32 = classes.iter().map(|(_, count)| count).sum();
+    let total_capacity: u32 = rooms.iter().map(|(_, capacity)| capacity).sum();
+    let room_list = rooms.iter().map(|(number, _)| number.as_str()).collect::<Vec<_>>().join(", ");
+
+    if total_students > total_capacity {
+        let hint = if rooms.len() == 1 { "; split the exam over more rooms" } else { "" };
+        return Err(AppError::new(
+            ErrorCode::CapacityExceeded,
+            format!(
+                "{} students exceed the capacity of {} in room(s) {}{}",
+                total_students, total_capacity, room_list, hint
+            ),
+        )
+        .with_details(serde_json::json!({
+            "student_count": total_students,
+            "room_capacity": total_capacity,
+            "room_numbers": rooms.iter().map(|(number, _)| number).collect::<Vec<_>>(),
+        })));
+    }
+
+    let mut plan: Vec<RoomAllocation> = rooms
+        .iter()
+        .map(|(room_number, capacity)| RoomAllocation {
+            transaction_code: String::new(),
+            room_number: room_number.clone(),
+            class_codes: vec![],
+            student_count: 0,
+            room_capacity: *capacity,
+        })
+        .collect();
+
+    let mut sorted: Vec<&(String, u32)> = classes.iter().collect();
+    sorted.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+    for (class_code, count) in sorted {
+        let room = plan
+            .iter_mut()
+            .find(|room| room.room_capacity - room.student_count >= *count)
+            .ok_or_else(|| {
+                AppError::new(
+                    ErrorCode::CapacityExceeded,
+                    format!(
+                        "Class {} ({} students) does not fit in the space left in room(s) {}",
+                        class_code, count, room_list
+                    ),
+                )
+                .with_details(serde_json::json!({ "class_code": class_code, "student_count": count }))
+            })?;
+        room.class_codes.push(class_code.clone());
+        room.student_count += count;
+    }
+
+    plan.retain(|room| !room.class_codes.is_empty());
+    Ok(plan)
+}
+
+// Finds students of the chosen classes who already sit another exam in the same date and shift.
+// Rows booked before transaction_detail existed have no details, so all students of their subject count.
+fn find_student_clashes(transaction: &mut Transaction, request: &ExamRequest) -> Result<Vec<StudentClash>, mysql::Error> {
+    let students = format!(
+        "SELECT nim FROM enrollments WHERE subject_code = ? AND class_code IN ({})",
+        placeholders(request.class_codes.len())
+    );
+    let query = format!(
+        r"SELECT td.nim, th.subject_code, th.transaction_code
+        FROM transaction_header th
+        JOIN transaction_detail td ON td.transaction_code = th.transaction_code
+        WHERE th.date = ? AND th.shift_code = ?
+            AND td.nim IN ({students})
+        UNION
+        SELECT e.nim, th.subject_code, th.transaction_code
+        FROM transaction_header th
+        JOIN enrollments e ON e.subject_code = th.subject_code
+        WHERE th.date = ? AND th.shift_code = ?
+            AND NOT EXISTS (SELECT 1 FROM transaction_detail td WHERE td.transaction_code = th.transaction_code)
+            AND e.nim IN ({students})
+        ORDER BY 1, 2",
+    );
+
+    let filter = values(&[request.date, request.shift_code, request.subject_code], request.class_codes);
+    let params: Vec<mysql::Value> = filter.iter().chain(filter.iter()).cloned().collect();
+
+    transaction.exec_map(query, params, |(nim, subject_code, transaction_code)| StudentClash {
+        nim,
+        subject_code,
+        transaction_code,
+    })
+}
+
+fn room_booked(request: &ExamRequest, room_number: &str, booked_by: Option<String>) -> AppError {
+    let message = match &booked_by {
+        Some(booked_by) => format!(
+            "Room {} is already booked by {} on {} shift {}",
+            room_number, booked_by, request.date, request.shift_code
+        ),
+        None => format!(
+            "Room {} was just booked by another user on {} shift {}",
+            room_number, request.date, request.shift_code
+        ),
+    };
+
+    AppError::new(ErrorCode::ConflictRoomBooked, message).with_details(serde_json::json!({
+        "room_number": room_number,
+        "date": request.date,
+        "shift_code": request.shift_code,
+        "transaction_code": booked_by,
+    }))
+}
+
+fn check_room_free(transaction: &mut Transaction, request: &ExamRequest, room_number: &str) -> AppResult<()> {
+    let booked_by: Option<String> = transaction
+        .exec_first(
+            "SELECT transaction_code FROM transaction_header WHERE date = ? AND shift_code = ? AND room_number = ?",
+            (request.date, request.shift_code, room_number),
+        )
+        .map_err(|e| AppError::database("Failed to query existing transactions", e))?;
+
+    match booked_by {
+        Some(booked_by) => Err(room_booked(request, room_number, Some(booked_by))),
+        None => Ok(()),
+    }
+}
+
+// Inserts the header row under a freshly generated code, skipping codes that
+// are already taken.
+fn insert_header(
+    transaction: &mut Transaction,
+    request: &ExamRequest,
+    room: &RoomAllocation,
+    exam_code: Option<&str>,
+    codes: &CodeFormat,
+    sequence: &mut dyn SequenceSource,
+) -> AppResult<String> {
+    codes.insert_with_next_code(request.date, sequence, MAX_CODE_ATTEMPTS, |transaction_code| {
+        let result = transaction.exec_drop(
+            "INSERT INTO transaction_header (transaction_code, exam_code, subject_code, shift_code, date, room_number) VALUES (?, ?, ?, ?, ?, ?)",
+            (
+                transaction_code,
+                exam_code.unwrap_or(transaction_code),
+                request.subject_code,
+                request.shift_code,
+                request.date,
+                &room.room_number,
+            ),
+        );
+
+        match result {
+            Ok(()) => Ok(true),
+            Err(e) if is_duplicate_key(&e, "PRIMARY") => Ok(false),
+            // uq_transaction_room_slot caught a booking made by another client since the room check
+            Err(e) if is_duplicate_key(&e, "uq_transaction_room_slot") => Err(room_booked(request, &room.room_number, None)),
+            Err(e) => Err(AppError::database("Failed to insert into transaction_header", e)),
+        }
+    })
+}
+
+/// Validates the request and books one `transaction_header` row per room that
+/// receives classes. Every row shares the `exam_code` of the first one.
+pub fn allocate(
+    transaction: &mut Transaction,
+    request: &ExamRequest,
+    room_numbers: &[String],
+    codes: &CodeFormat,
+    sequence: &mut dyn SequenceSource,
+) -> AppResult<Vec<RoomAllocation>> {
+    if request.class_codes.is_empty() {
+        return Err(AppError::validation("Select at least one class to allocate"));
+    }
+    if room_numbers.is_empty() {
+        return Err(AppError::validation("Select at least one room to allocate"));
+    }
+    if let Some(class_code) = first_duplicate(request.class_codes.iter()) {
+        return Err(AppError::validation(format!("Class {} is listed more than once", class_code)));
+    }
+    if let Some(room_number) = first_duplicate(room_numbers.iter()) {
+        return Err(AppError::validation(format!("Room {} is listed more than once", room_number)));
+    }
+
+    let classes = count_students(transaction, request)?;
+    let rooms = room_capacities(transaction, room_numbers)?;
+    let mut plan = plan_rooms(&classes, &rooms)?;
+
+    for room in &plan {
+        check_room_free(transaction, request, &room.room_number)?;
+    }
+
+    let clashes = find_student_clashes(transaction, request)
+        .map_err(|e| AppError::database("Failed to check student clashes", e))?;
+
+    if !clashes.is_empty() {
+        println!("Student clashes found: {:?}", clashes);
+        return Err(AppError::new(
+            ErrorCode::ConflictStudentClash,
+            format!(
+                "{} student(s) already have an exam on {} shift {}",
+                clashes.len(),
+                request.date,
+                request.shift_code
+            ),
+        )
+        .with_details(serde_json::json!({ "clashes": clashes })));
+    }
+
+    let mut exam_code: Option<String> = None;
+    for room in plan.iter_mut() {
+        room.transaction_code = insert_header(transaction, request, room, exam_code.as_deref(), codes, sequence)?;
+        let exam_code = exam_code.get_or_insert_with(|| room.transaction_code.clone());
+
+        println!("Booked {} (exam {}) in room {}", room.transaction_code, exam_code, room.room_number);
+
+        // Seat every student of the room's classes; a student in two of them keeps the first class
+        transaction
+            .exec_drop(
+                format!(
+                    r"INSERT INTO transaction_detail (transaction_code, class_code, nim)
+                    SELECT ?, MIN(class_code), nim FROM enrollments
+                    WHERE subject_code = ? AND class_code IN ({})
+                    GROUP BY nim",
+                    placeholders(room.class_codes.len())
+                ),
+                values(&[&room.transaction_code, request.subject_code], &room.class_codes),
+            )
+            .map_err(|e| AppError::database("Failed to insert into transaction_detail", e))?;
+    }
+
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(list: &[(&str, u32)]) -> Vec<(String, u32)> {
+        list.iter().map(|(code, count)| (code.to_string(), *count)).collect()
+    }
+
+    // Room number, classes and student count of each planned room
+    fn summary(plan: &[RoomAllocation]) -> Vec<(&str, Vec<&str>, u32)> {
+        plan.iter()
+            .map(|room| {
+                let classes = room.class_codes.iter().map(String::as_str).collect();
+                (room.room_number.as_str(), classes, room.student_count)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fills_a_room_exactly() {
+        let plan = plan_rooms(&entries(&[("LA01", 20), ("LB01", 20)]), &entries(&[("601", 40)])).unwrap();
+
+        assert_eq!(summary(&plan), [("601", vec!["LA01", "LB01"], 40)]);
+        assert_eq!(plan[0].room_capacity, 40);
+    }
+
+    #[test]
+    fn splits_classes_over_rooms_largest_first() {
+        let classes = entries(&[("LA01", 10), ("LB01", 30), ("LC01", 25)]);
+        let rooms = entries(&[("601", 35), ("602", 30), ("603", 40)]);
+
+        let plan = plan_rooms(&classes, &rooms).unwrap();
+
+        assert_eq!(summary(&plan), [("601", vec!["LB01"], 30), ("602", vec!["LC01"], 25), ("603", vec!["LA01"], 10)]);
+    }
+
+    #[test]
+    fn leaves_unused_rooms_out() {
+        let plan = plan_rooms(&entries(&[("LA01", 15)]), &entries(&[("601", 20), ("602", 20)])).unwrap();
+
+        assert_eq!(summary(&plan), [("601", vec!["LA01"], 15)]);
+    }
+
+    #[test]
+    fn rejects_more_students_than_seats() {
+        let error = plan_rooms(&entries(&[("LA01", 30), ("LB01", 15)]), &entries(&[("601", 40)])).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::CapacityExceeded);
+        assert!(error.message.contains("split the exam over more rooms"), "{}", error.message);
+    }
+
+    #[test]
+    fn rejects_a_class_that_fits_no_single_room() {
+        // 40 seats in total, but classes are never split across rooms
+        let error = plan_rooms(&entries(&[("LA01", 25)]), &entries(&[("601", 20), ("602", 20)])).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::CapacityExceeded);
+        assert!(error.message.contains("Class LA01"), "{}", error.message);
+    }
+
+    #[test]
+    fn rejects_a_class_listed_twice() {
+        let error = plan_rooms(&entries(&[("LA01", 10), ("LB01", 10), ("LA01", 10)]), &entries(&[("601", 40)])).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::ValidationFailed);
+        assert_eq!(error.message, "Class LA01 is listed more than once");
+    }
+
+    #[test]
+    fn handles_empty_input() {
+        assert!(plan_rooms(&[], &entries(&[("601", 40)])).unwrap().is_empty());
+        assert!(plan_rooms(&[], &[]).unwrap().is_empty());
+
+        let error = plan_rooms(&entries(&[("LA01", 1)]), &[]).unwrap_err();
+        assert_eq!(error.code, ErrorCode::CapacityExceeded);
+    }
+}
@@ -0,0 +1,62 @@
+use mysql::prelude::*;
+use mysql::PooledConn;
+use serde::Serialize;
+
+use crate::error::{AppError, AppResult};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditEntry {
+    pub id: u64,
+    /// bn_number of whoever acted.
+    pub actor: String,
+    pub action: String,
+    /// bn_number the action was about, if any.
+    pub target: Option<String>,
+    pub machine: String,
+    pub details: serde_json::Value,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+}
+
+/// Appends to the audit trail. Takes any `Queryable` so it can be part of the
+/// transaction making the change.
+pub fn record(
+    conn: &mut impl Queryable,
+    actor: &str,
+    machine: &str,
+    action: &str,
+    target: Option<&str>,
+    details: serde_json::Value,
+) -> AppResult<()> {
+    conn.exec_drop(
+        "INSERT INTO audit_log (actor, machine, action, target, details) VALUES (?, ?, ?, ?, ?)",
+        (actor, machine, action, target, details.to_string()),
+    )
+    .map_err(|e| AppError::database("Failed to write audit log", e))
+}
+
+/// The newest `limit` entries, only those about `target` if given.
+pub fn entries(conn: &mut PooledConn, target: Option<&str>, limit: u32) -> AppResult<Vec<AuditEntry>> {
+    conn.exec_map(
+        r"SELECT id, actor, action, target, machine, details, UNIX_TIMESTAMP(created_at)
+        FROM audit_log
+        WHERE ? IS NULL OR target = ?
+        ORDER BY id DESC
+        LIMIT ?",
+        (target, target, limit),
+        |(id, actor, action, target, machine, details, created_at): (u64, String, String, Option<String>, String, Option<String>, u64)| {
+            AuditEntry {
+                id,
+                actor,
+                action,
+                target,
+                machine,
+                details: details
+                    .and_then(|details| serde_json::from_str(&details).ok())
+                    .unwrap_or(serde_json::Value::Null),
+                created_at,
+            }
+        },
+    )
+    .map_err(|e| AppError::database("Failed to query audit log", e))
+}
@@ -0,0 +1,267 @@
+use mysql::prelude::*;
+use mysql::Pool;
+
+use crate::error::{AppError, AppResult};
+
+pub const DEFAULT_TRANSACTION_CODE_FORMAT: &str = "TH-{yyyy}-{mm}-{seq:4}";
+
+/// Hands out increasing numbers per period. Every call must return a number
+/// that was never returned before for the same period, even across clients.
+pub trait SequenceSource {
+    fn next(&mut self, period: &str) -> AppResult<u64>;
+}
+
+/// Counters kept in `transaction_sequences`, incremented atomically by MySQL.
+///
+/// Each call commits on its own connection, so an allocation that is later
+/// rolled back leaves a gap in the sequence instead of a reused number.
+pub struct MySqlSequence {
+    pool: Pool,
+}
+
+impl MySqlSequence {
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+}
+
+impl SequenceSource for MySqlSequence {
+    fn next(&mut self, period: &str) -> AppResult<u64> {
+        let mut conn = self.pool.get_conn().map_err(|e| AppError::database("Failed to get connection", e))?;
+
+        conn.exec_drop(
+            r"INSERT INTO transaction_sequences (period, last_value) VALUES (?, LAST_INSERT_ID(1))
+            ON DUPLICATE KEY UPDATE last_value = LAST_INSERT_ID(last_value + 1)",
+            (period,),
+        )
+        .map_err(|e| AppError::database(&format!("Failed to advance sequence {}", period), e))?;
+
+        Ok(conn.last_insert_id())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Literal(String),
+    Year,
+    Month,
+    Day,
+    Seq(usize),
+}
+
+/// A code pattern such as `TH-{yyyy}-{mm}-{seq:4}`.
+///
+/// `{yyyy}`, `{mm}` and `{dd}` come from the exam date and `{seq:N}` is the
+/// sequence number zero-padded to N digits (`{seq}` pads to 4). The sequence
+/// restarts for every distinct value of the date parts, so the pattern above
+/// numbers codes per exam month.
+#[derive(Clone, Debug)]
+pub struct CodeFormat {
+    tokens: Vec<Token>,
+}
+
+impl CodeFormat {
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        let mut tokens = Vec::new();
+        let mut rest = pattern;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                tokens.push(Token::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| format!("Unclosed '{{' in code format {}", pattern))?;
+
+            tokens.push(match &rest[start + 1..end] {
+                "yyyy" => Token::Year,
+                "mm" => Token::Month,
+                "dd" => Token::Day,
+                "seq" => Token::Seq(4),
+                other => match other.strip_prefix("seq:").and_then(|width| width.parse().ok()) {
+                    Some(width) => Token::Seq(width),
+                    None => return Err(format!("Unknown placeholder {{{}}} in code format {}", other, pattern)),
+                },
+            });
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            tokens.push(Token::Literal(rest.to_string()));
+        }
+
+        match tokens.iter().filter(|token| matches!(token, Token::Seq(_))).count() {
+            1 => Ok(Self { tokens }),
+            _ => Err(format!("Code format {} must contain exactly one {{seq}} placeholder", pattern)),
+        }
+    }
+
+    fn render(&self, date: &str, seq: Option<u64>) -> AppResult<String> {
+        let parts: Vec<&str> = date.split('-').collect();
+        let valid = parts.len() == 3
+            && [4, 2, 2].iter().zip(&parts).all(|(len, part)| part.len() == *len && part.chars().all(|c| c.is_ascii_digit()));
+        if !valid {
+            return Err(AppError::validation(format!("Invalid exam date {}, expected YYYY-MM-DD", date)));
+        }
+
+        Ok(self
+            .tokens
+            .iter()
+            .map(|token| match token {
+                Token::Literal(text) => text.clone(),
+                Token::Year => parts[0].to_string(),
+                Token::Month => parts[1].to_string(),
+                Token::Day => parts[2].to_string(),
+                Token::Seq(width) => seq.map(|seq| format!("{:0width$}", seq, width = width)).unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    /// Formats the next code for an exam on `date` (`YYYY-MM-DD`).
+    pub fn next_code(&self, date: &str, source: &mut dyn SequenceSource) -> AppResult<String> {
+        let period = self.render(date, None)?;
+        let seq = source.next(&period)?;
+        self.render(date, Some(seq))
+    }
+
+    /// Hands fresh codes to `insert` until it stores one and returns that code.
+    /// `insert` returns `Ok(false)` when the code is already taken (e.g. by a
+    /// row imported from an older code format), and the next one is tried, up
+    /// to `max_attempts` codes.
+    pub fn insert_with_next_code(
+        &self,
+        date: &str,
+        source: &mut dyn SequenceSource,
+        max_attempts: usize,
+        mut insert: impl FnMut(&str) -> AppResult<bool>,
+    ) -> AppResult<String> {
+        for _ in 0..max_attempts {
+            let code = self.next_code(date, source)?;
+            if insert(&code)? {
+                return Ok(code);
+            }
+            println!("Code {} is already taken, retrying", code);
+        }
+
+        Err(AppError::internal(format!("No free code found after {} attempts", max_attempts)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::error::ErrorCode;
+
+    // Counts per period in memory and remembers which periods were asked for
+    #[derive(Default)]
+    struct FakeSequence {
+        counters: HashMap<String, u64>,
+        periods: Vec<String>,
+    }
+
+    impl SequenceSource for FakeSequence {
+        fn next(&mut self, period: &str) -> AppResult<u64> {
+            self.periods.push(period.to_string());
+            let counter = self.counters.entry(period.to_string()).or_insert(0);
+            *counter += 1;
+            Ok(*counter)
+        }
+    }
+
+    #[test]
+    fn parse_rejects_invalid_patterns() {
+        for (pattern, expected) in [
+            ("TH-{yyyy", "Unclosed '{'"),
+            ("TH-{yy}-{seq}", "Unknown placeholder {yy}"),
+            ("TH-{seq:x}", "Unknown placeholder {seq:x}"),
+            ("TH-{yyyy}-{mm}", "exactly one {seq}"),
+            ("{seq}-{seq:2}", "exactly one {seq}"),
+        ] {
+            let error = CodeFormat::parse(pattern).unwrap_err();
+            assert!(error.contains(expected), "{}: {}", pattern, error);
+        }
+    }
+
+    #[test]
+    fn pads_the_sequence_to_its_width() {
+        for (pattern, seq, expected) in [
+            ("TH{seq}", 7, "TH0007"),
+            ("TH{seq:3}", 7, "TH007"),
+            ("TH{seq:1}", 7, "TH7"),
+            ("TH{seq:2}", 123, "TH123"),
+        ] {
+            let format = CodeFormat::parse(pattern).unwrap();
+            assert_eq!(format.render("2024-06-01", Some(seq)).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn numbers_codes_per_exam_month() {
+        let format = CodeFormat::parse(DEFAULT_TRANSACTION_CODE_FORMAT).unwrap();
+        let mut source = FakeSequence::default();
+
+        let codes: Vec<String> = ["2024-06-01", "2024-06-30", "2024-07-01", "2024-06-15"]
+            .iter()
+            .map(|date| format.next_code(date, &mut source).unwrap())
+            .collect();
+
+        assert_eq!(codes, ["TH-2024-06-0001", "TH-2024-06-0002", "TH-2024-07-0001", "TH-2024-06-0003"]);
+        assert_eq!(source.periods, ["TH-2024-06-", "TH-2024-06-", "TH-2024-07-", "TH-2024-06-"]);
+    }
+
+    #[test]
+    fn rejects_malformed_dates() {
+        let format = CodeFormat::parse(DEFAULT_TRANSACTION_CODE_FORMAT).unwrap();
+        let mut source = FakeSequence::default();
+
+        for date in ["2024-6-01", "24-06-01", "2024/06/01", "2024-06-0a", ""] {
+            let error = format.next_code(date, &mut source).unwrap_err();
+            assert_eq!(error.code, ErrorCode::ValidationFailed, "{}", date);
+        }
+        assert!(source.periods.is_empty());
+    }
+
+    #[test]
+    fn retries_after_a_duplicate_key() {
+        let format = CodeFormat::parse(DEFAULT_TRANSACTION_CODE_FORMAT).unwrap();
+        let mut source = FakeSequence::default();
+        let taken = ["TH-2024-06-0001", "TH-2024-06-0002"];
+        let mut tried = Vec::new();
+
+        let code = format
+            .insert_with_next_code("2024-06-01", &mut source, 5, |code| {
+                tried.push(code.to_string());
+                Ok(!taken.contains(&code))
+            })
+            .unwrap();
+
+        assert_eq!(code, "TH-2024-06-0003");
+        assert_eq!(tried, ["TH-2024-06-0001", "TH-2024-06-0002", "TH-2024-06-0003"]);
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let format = CodeFormat::parse(DEFAULT_TRANSACTION_CODE_FORMAT).unwrap();
+        let mut source = FakeSequence::default();
+
+        let error = format.insert_with_next_code("2024-06-01", &mut source, 3, |_| Ok(false)).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::Internal);
+        assert_eq!(source.periods.len(), 3);
+    }
+
+    #[test]
+    fn does_not_retry_other_errors() {
+        let format = CodeFormat::parse(DEFAULT_TRANSACTION_CODE_FORMAT).unwrap();
+        let mut source = FakeSequence::default();
+
+        let error = format
+            .insert_with_next_code("2024-06-01", &mut source, 5, |_| Err(AppError::validation("Room booked")))
+            .unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::ValidationFailed);
+        assert_eq!(source.periods.len(), 1);
+    }
+}
@@ -0,0 +1,557 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use mysql::{Opts, OptsBuilder, PoolConstraints, PoolOpts};
+use serde::Deserialize;
+
+use crate::codes::{CodeFormat, DEFAULT_TRANSACTION_CODE_FORMAT};
+use crate::sync;
+
+const CONFIG_FILE_NAME: &str = "sr-exam.toml";
+
+/// `sr-exam.toml` next to the executable, so it does not depend on the
+/// directory the app was launched from.
+fn default_config_path() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(CONFIG_FILE_NAME)))
+        .unwrap_or_else(|| PathBuf::from(CONFIG_FILE_NAME))
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub user: String,
+    pub password: String,
+    pub host: String,
+    pub port: u16,
+    pub name: String,
+    pub pool_min: usize,
+    pub pool_max: usize,
+    pub connect_timeout_secs: u64,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            user: "root".to_string(),
+            password: "".to_string(),
+            host: "localhost".to_string(),
+            port: 3306,
+            name: "sr-exam".to_string(),
+            pool_min: 10,
+            pool_max: 100,
+            connect_timeout_secs: 5,
+        }
+    }
+}
+
+impl DatabaseConfig {
+    pub fn opts(&self) -> Opts {
+        self.opts_with_pool_min(self.pool_min)
+    }
+
+    /// Options for a pool that opens no connection until one is requested, so
+    /// it can be created while the server is unreachable.
+    pub fn lazy_opts(&self) -> Opts {
+        self.opts_with_pool_min(0)
+    }
+
+    fn opts_with_pool_min(&self, pool_min: usize) -> Opts {
+        // Validated by AppConfig::validate, so the constraints are always well-formed
+        let constraints = PoolConstraints::new(pool_min, self.pool_max).expect("Invalid pool size");
+
+        OptsBuilder::new()
+            .user(Some(&self.user))
+            .pass(Some(&self.password))
+            .ip_or_hostname(Some(&self.host))
+            .tcp_port(self.port)
+            .db_name(Some(&self.name))
+            .tcp_connect_timeout(Some(Duration::from_secs(self.connect_timeout_secs)))
+            .pool_opts(PoolOpts::default().with_constraints(constraints))
+            .into()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GraphqlConfig {
+    pub endpoint: String,
+    pub timeout_secs: u64,
+}
+
+impl Default for GraphqlConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: "https://academic-slc.apps.binus.ac.id/tpa-241/query".to_string(),
+            timeout_secs: 15,
+        }
+    }
+}
+
+impl GraphqlConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExamConfig {
+    pub transaction_code_format: String,
+}
+
+impl Default for ExamConfig {
+    fn default() -> Self {
+        Self {
+            transaction_code_format: DEFAULT_TRANSACTION_CODE_FORMAT.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoginConfig {
+    /// Failed attempts on one account that lock it.
+    pub max_failures: u32,
+    /// How long a lockout lasts; failures older than this are forgotten.
+    pub lockout_minutes: u32,
+    /// Wait after the first failure, doubled for every further one.
+    pub base_delay_secs: u64,
+    pub max_delay_secs: u64,
+}
+
+impl Default for LoginConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            lockout_minutes: 15,
+            base_delay_secs: 1,
+            max_delay_secs: 30,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordConfig {
+    pub min_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    /// How many of the user's previous passwords may not be reused.
+    pub history: usize,
+    /// How long a reset code issued by an admin stays valid.
+    pub reset_code_minutes: u32,
+    /// Algorithm for new hashes. Older hashes are upgraded at the next login.
+    pub algorithm: HashAlgorithm,
+    pub bcrypt_cost: u32,
+    pub argon2_memory_kib: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Bcrypt,
+    Argon2id,
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, ()> {
+        match value {
+            "bcrypt" => Ok(Self::Bcrypt),
+            "argon2id" => Ok(Self::Argon2id),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Default for PasswordConfig {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: false,
+            history: 5,
+            reset_code_minutes: 30,
+            algorithm: HashAlgorithm::Argon2id,
+            bcrypt_cost: 12,
+            // OWASP's recommended minimum for Argon2id
+            argon2_memory_kib: 19 * 1024,
+            argon2_iterations: 2,
+            argon2_parallelism: 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// A session unused for this long is closed.
+    pub idle_timeout_minutes: u64,
+    /// How long a "remember me" login survives restarts; 0 turns it off.
+    pub remember_me_days: u64,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout_minutes: 30,
+            remember_me_days: 7,
+        }
+    }
+}
+
+impl SessionConfig {
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_minutes * 60)
+    }
+
+    pub fn remember_me(&self) -> Duration {
+        Duration::from_secs(self.remember_me_days * 24 * 60 * 60)
+    }
+}
+
+/// What sync does when upstream changed a column that was also edited locally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    /// Upstream always wins and the local edit is dropped.
+    Upstream,
+    /// The local edit is kept for good.
+    Local,
+    /// The local edit is kept until the upstream value changes.
+    UpstreamIfChanged,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyncConfig {
+    /// Minutes between background syncs; 0 syncs only at startup and on request.
+    pub interval_minutes: u64,
+    /// A sync that would delete more than this fraction of a table's rows is
+    /// aborted, since upstream most likely returned partial data.
+    pub max_delete_fraction: f64,
+    /// Policies keyed by "table.column", replacing the built-in ones.
+    pub conflicts: HashMap<String, ConflictPolicy>,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            interval_minutes: 15,
+            max_delete_fraction: 0.5,
+            conflicts: HashMap::new(),
+        }
+    }
+}
+
+impl SyncConfig {
+    pub fn interval(&self) -> Option<Duration> {
+        (self.interval_minutes > 0).then(|| Duration::from_secs(self.interval_minutes * 60))
+    }
+}
+
+/// Settings read from `sr-exam.toml` next to the executable (or the file named
+/// by `SR_EXAM_CONFIG`).
+/// Every key is optional; missing keys keep their defaults.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    pub database: DatabaseConfig,
+    pub graphql: GraphqlConfig,
+    pub exams: ExamConfig,
+    pub login: LoginConfig,
+    pub password: PasswordConfig,
+    pub session: SessionConfig,
+    pub sync: SyncConfig,
+    /// Overrides for `Permissions`, keyed by command name.
+    pub permissions: HashMap<String, Vec<String>>,
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub source: String,
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Invalid configuration ({}):", self.source)?;
+        for problem in &self.problems {
+            writeln!(f, "  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Looks up an environment variable; tests pass their own instead of touching the process environment.
+type EnvVar<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+fn env_override<T: FromStr>(var: EnvVar, name: &str, target: &mut T, problems: &mut Vec<String>) {
+    if let Some(value) = var(name) {
+        match value.parse() {
+            Ok(value) => *target = value,
+            Err(_) => problems.push(format!("{} has an invalid value '{}'", name, value)),
+        }
+    }
+}
+
+impl AppConfig {
+    /// Reads the config file when it exists, applies `SR_EXAM_*` environment
+    /// overrides and validates the result.
+    pub fn load() -> Result<Self, ConfigError> {
+        let explicit_path = std::env::var("SR_EXAM_CONFIG").ok();
+        let path = explicit_path
+            .clone()
+            .unwrap_or_else(|| default_config_path().to_string_lossy().into_owned());
+
+        let mut config = match fs::read_to_string(&path) {
+            Ok(contents) => {
+                println!("Loading config from {}", path);
+                toml::from_str(&contents).map_err(|e| ConfigError {
+                    source: path.clone(),
+                    problems: vec![e.to_string()],
+                })?
+            }
+            // A file named explicitly must exist; the default one is optional
+            Err(e) if explicit_path.is_some() => {
+                return Err(ConfigError {
+                    source: path,
+                    problems: vec![format!("Failed to read SR_EXAM_CONFIG: {}", e)],
+                })
+            }
+            Err(_) => {
+                println!("No config file at {}, using defaults", path);
+                AppConfig::default()
+            }
+        };
+
+        let mut problems = Vec::new();
+        config.apply_env(&|name| std::env::var(name).ok(), &mut problems);
+        problems.extend(config.validate());
+
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError { source: path, problems })
+        }
+    }
+
+    fn apply_env(&mut self, var: EnvVar, problems: &mut Vec<String>) {
+        env_override(var, "SR_EXAM_DB_USER", &mut self.database.user, problems);
+        env_override(var, "SR_EXAM_DB_PASSWORD", &mut self.database.password, problems);
+        env_override(var, "SR_EXAM_DB_HOST", &mut self.database.host, problems);
+        env_override(var, "SR_EXAM_DB_PORT", &mut self.database.port, problems);
+        env_override(var, "SR_EXAM_DB_NAME", &mut self.database.name, problems);
+        env_override(var, "SR_EXAM_DB_POOL_MIN", &mut self.database.pool_min, problems);
+        env_override(var, "SR_EXAM_DB_POOL_MAX", &mut self.database.pool_max, problems);
+        env_override(var, "SR_EXAM_DB_CONNECT_TIMEOUT_SECS", &mut self.database.connect_timeout_secs, problems);
+        env_override(var, "SR_EXAM_GRAPHQL_ENDPOINT", &mut self.graphql.endpoint, problems);
+        env_override(var, "SR_EXAM_GRAPHQL_TIMEOUT_SECS", &mut self.graphql.timeout_secs, problems);
+        env_override(var, "SR_EXAM_TRANSACTION_CODE_FORMAT", &mut self.exams.transaction_code_format, problems);
+        env_override(var, "SR_EXAM_LOGIN_MAX_FAILURES", &mut self.login.max_failures, problems);
+        env_override(var, "SR_EXAM_LOGIN_LOCKOUT_MINUTES", &mut self.login.lockout_minutes, problems);
+        env_override(var, "SR_EXAM_PASSWORD_MIN_LENGTH", &mut self.password.min_length, problems);
+        env_override(var, "SR_EXAM_PASSWORD_HISTORY", &mut self.password.history, problems);
+        env_override(var, "SR_EXAM_PASSWORD_RESET_CODE_MINUTES", &mut self.password.reset_code_minutes, problems);
+        env_override(var, "SR_EXAM_PASSWORD_ALGORITHM", &mut self.password.algorithm, problems);
+        env_override(var, "SR_EXAM_SESSION_IDLE_TIMEOUT_MINUTES", &mut self.session.idle_timeout_minutes, problems);
+        env_override(var, "SR_EXAM_SESSION_REMEMBER_ME_DAYS", &mut self.session.remember_me_days, problems);
+        env_override(var, "SR_EXAM_SYNC_INTERVAL_MINUTES", &mut self.sync.interval_minutes, problems);
+        env_override(var, "SR_EXAM_SYNC_MAX_DELETE_FRACTION", &mut self.sync.max_delete_fraction, problems);
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.database.user.trim().is_empty() {
+            problems.push("database.user must not be empty".to_string());
+        }
+        if self.database.host.trim().is_empty() {
+            problems.push("database.host must not be empty".to_string());
+        }
+        if self.database.name.trim().is_empty() {
+            problems.push("database.name must not be empty".to_string());
+        }
+        if self.database.port == 0 {
+            problems.push("database.port must be between 1 and 65535".to_string());
+        }
+        if self.database.pool_max == 0 {
+            problems.push("database.pool_max must be at least 1".to_string());
+        }
+        if self.database.pool_min > self.database.pool_max {
+            problems.push(format!(
+                "database.pool_min ({}) must not exceed database.pool_max ({})",
+                self.database.pool_min, self.database.pool_max
+            ));
+        }
+        if self.database.connect_timeout_secs == 0 {
+            problems.push("database.connect_timeout_secs must be at least 1".to_string());
+        }
+        if !(self.graphql.endpoint.starts_with("http://") || self.graphql.endpoint.starts_with("https://")) {
+            problems.push(format!("graphql.endpoint '{}' must be an http(s) URL", self.graphql.endpoint));
+        }
+        if self.graphql.timeout_secs == 0 {
+            problems.push("graphql.timeout_secs must be at least 1".to_string());
+        }
+        if self.login.max_failures == 0 {
+            problems.push("login.max_failures must be at least 1".to_string());
+        }
+        if self.login.lockout_minutes == 0 {
+            problems.push("login.lockout_minutes must be at least 1".to_string());
+        }
+        if self.login.base_delay_secs > self.login.max_delay_secs {
+            problems.push(format!(
+                "login.base_delay_secs ({}) must not exceed login.max_delay_secs ({})",
+                self.login.base_delay_secs, self.login.max_delay_secs
+            ));
+        }
+        if self.password.min_length == 0 {
+            problems.push("password.min_length must be at least 1".to_string());
+        }
+        if self.password.reset_code_minutes == 0 {
+            problems.push("password.reset_code_minutes must be at least 1".to_string());
+        }
+        if !(4..=31).contains(&self.password.bcrypt_cost) {
+            problems.push("password.bcrypt_cost must be between 4 and 31".to_string());
+        }
+        if self.password.argon2_iterations == 0 || self.password.argon2_parallelism == 0 {
+            problems.push("password.argon2_iterations and password.argon2_parallelism must be at least 1".to_string());
+        }
+        if self.password.argon2_memory_kib < 8 * self.password.argon2_parallelism {
+            problems.push("password.argon2_memory_kib must be at least 8 times password.argon2_parallelism".to_string());
+        }
+        if self.session.idle_timeout_minutes == 0 {
+            problems.push("session.idle_timeout_minutes must be at least 1".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.sync.max_delete_fraction) {
+            problems.push("sync.max_delete_fraction must be between 0 and 1".to_string());
+        }
+        for column in self.sync.conflicts.keys() {
+            if !sync::is_mirrored(column) {
+                problems.push(format!("sync.conflicts: '{}' is not a synced table.column", column));
+            }
+        }
+        if let Err(e) = CodeFormat::parse(&self.exams.transaction_code_format) {
+            problems.push(format!("exams.transaction_code_format: {}", e));
+        }
+
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Makes one setting of a valid config invalid
+    type Breaks = fn(&mut AppConfig);
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn accepts_the_defaults() {
+        assert_eq!(AppConfig::default().validate(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_each_invalid_setting() {
+        let cases: Vec<(Breaks, &str)> = vec![
+            (|c| c.database.user = " ".to_string(), "database.user"),
+            (|c| c.database.host = String::new(), "database.host"),
+            (|c| c.database.name = String::new(), "database.name"),
+            (|c| c.database.port = 0, "database.port"),
+            (
+                |c| {
+                    c.database.pool_min = 0;
+                    c.database.pool_max = 0;
+                },
+                "database.pool_max",
+            ),
+            (|c| c.database.pool_min = c.database.pool_max + 1, "database.pool_min"),
+            (|c| c.database.connect_timeout_secs = 0, "database.connect_timeout_secs"),
+            (|c| c.graphql.endpoint = "ftp://example.com".to_string(), "graphql.endpoint"),
+            (|c| c.graphql.timeout_secs = 0, "graphql.timeout_secs"),
+            (|c| c.login.max_failures = 0, "login.max_failures"),
+            (|c| c.login.lockout_minutes = 0, "login.lockout_minutes"),
+            (|c| c.login.base_delay_secs = c.login.max_delay_secs + 1, "login.base_delay_secs"),
+            (|c| c.password.min_length = 0, "password.min_length"),
+            (|c| c.password.reset_code_minutes = 0, "password.reset_code_minutes"),
+            (|c| c.password.bcrypt_cost = 3, "password.bcrypt_cost"),
+            (|c| c.password.argon2_iterations = 0, "password.argon2_iterations"),
+            (|c| c.password.argon2_memory_kib = 7, "password.argon2_memory_kib"),
+            (|c| c.session.idle_timeout_minutes = 0, "session.idle_timeout_minutes"),
+            (|c| c.sync.max_delete_fraction = 1.5, "sync.max_delete_fraction"),
+            (
+                |c| {
+                    c.sync.conflicts.insert("users.nope".to_string(), ConflictPolicy::Local);
+                },
+                "sync.conflicts",
+            ),
+            (|c| c.exams.transaction_code_format = String::new(), "exams.transaction_code_format"),
+        ];
+        for (break_config, setting) in cases {
+            let mut config = AppConfig::default();
+            break_config(&mut config);
+            let problems = config.validate();
+            assert_eq!(problems.len(), 1, "{}: {:?}", setting, problems);
+            assert!(problems[0].starts_with(setting), "{}: {:?}", setting, problems);
+        }
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let mut config = AppConfig::default();
+        let mut problems = Vec::new();
+        config.apply_env(
+            &env(&[
+                ("SR_EXAM_DB_HOST", "db.internal"),
+                ("SR_EXAM_DB_PORT", "3307"),
+                ("SR_EXAM_PASSWORD_ALGORITHM", "bcrypt"),
+                ("SR_EXAM_SYNC_INTERVAL_MINUTES", "0"),
+            ]),
+            &mut problems,
+        );
+
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(config.database.host, "db.internal");
+        assert_eq!(config.database.port, 3307);
+        assert_eq!(config.password.algorithm, HashAlgorithm::Bcrypt);
+        assert_eq!(config.sync.interval(), None);
+        // Variables that are not set leave the value alone
+        assert_eq!(config.database.user, DatabaseConfig::default().user);
+    }
+
+    #[test]
+    fn reports_environment_values_that_do_not_parse() {
+        let mut config = AppConfig::default();
+        let mut problems = Vec::new();
+        config.apply_env(&env(&[("SR_EXAM_DB_PORT", "70000"), ("SR_EXAM_PASSWORD_ALGORITHM", "md5")]), &mut problems);
+
+        assert_eq!(
+            problems,
+            vec![
+                "SR_EXAM_DB_PORT has an invalid value '70000'".to_string(),
+                "SR_EXAM_PASSWORD_ALGORITHM has an invalid value 'md5'".to_string(),
+            ]
+        );
+        assert_eq!(config.database.port, DatabaseConfig::default().port);
+    }
+}
@@ -0,0 +1,124 @@
+use std::fmt;
+
+use serde::Serialize;
+
+/// MySQL error code for a duplicate key on insert.
+pub const ER_DUP_ENTRY: u16 = 1062;
+
+/// Stable identifiers the frontend can switch on. Never rename a variant;
+/// the serialized names are part of the command API.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// MySQL could not be reached.
+    DatabaseUnavailable,
+    /// MySQL was reached but the query failed.
+    DatabaseError,
+    /// The GraphQL endpoint failed, timed out or returned no data.
+    NetworkError,
+    Unauthenticated,
+    /// The session was idle for too long and has been closed.
+    SessionExpired,
+    /// Logged in with a default password that must be changed first.
+    PasswordChangeRequired,
+    /// The user's role requires two-factor and they have not enrolled yet.
+    TwoFactorEnrollmentRequired,
+    Forbidden,
+    InvalidCredentials,
+    /// Too many failed logins; the account is locked for a while.
+    AccountLocked,
+    /// The caller must wait before the next login attempt.
+    TooManyAttempts,
+    /// An admin suspended the account.
+    AccountSuspended,
+    NotFound,
+    ValidationFailed,
+    /// Another sync is still running.
+    SyncInProgress,
+    ConflictRoomBooked,
+    ConflictStudentClash,
+    CapacityExceeded,
+    Internal,
+}
+
+/// Error returned by every Tauri command, serialized as
+/// `{ "code": "...", "message": "...", "details": ... }`.
+#[derive(Clone, Debug, Serialize)]
+pub struct AppError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub details: Option<serde_json::Value>,
+}
+
+pub type AppResult<T> = Result<T, AppError>;
+
+impl AppError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: impl Serialize) -> Self {
+        self.details = serde_json::to_value(details).ok();
+        self
+    }
+
+    pub fn unauthenticated() -> Self {
+        Self::new(ErrorCode::Unauthenticated, "Not logged in")
+    }
+
+    pub fn validation(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::ValidationFailed, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NotFound, message)
+    }
+
+    pub fn network(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NetworkError, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Internal, message)
+    }
+
+    /// Wraps a MySQL error with what the command was doing when it failed.
+    pub fn database(context: &str, error: mysql::Error) -> Self {
+        let code = match error {
+            mysql::Error::IoError(_) | mysql::Error::DriverError(_) | mysql::Error::UrlError(_) => {
+                ErrorCode::DatabaseUnavailable
+            }
+            _ => ErrorCode::DatabaseError,
+        };
+        Self::new(code, format!("{}: {}", context, error))
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<mysql::Error> for AppError {
+    fn from(error: mysql::Error) -> Self {
+        Self::database("Database query failed", error)
+    }
+}
+
+impl<T> From<std::sync::PoisonError<T>> for AppError {
+    fn from(error: std::sync::PoisonError<T>) -> Self {
+        Self::internal(format!("Failed to lock mutex: {}", error))
+    }
+}
+
+/// True when `error` is a duplicate-key violation of the named key.
+pub fn is_duplicate_key(error: &mysql::Error, key: &str) -> bool {
+    matches!(error, mysql::Error::MySqlError(err) if err.code == ER_DUP_ENTRY && err.message.contains(key))
+}
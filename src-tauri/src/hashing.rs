@@ -0,0 +1,155 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use mysql::prelude::*;
+use mysql::PooledConn;
+
+use crate::config::{HashAlgorithm, PasswordConfig};
+use crate::error::{AppError, AppResult};
+
+// Hashes are self-describing: bcrypt's "$2b$<cost>$..." and the PHC string
+// "$argon2id$v=19$m=<KiB>,t=<iterations>,p=<parallelism>$..." both carry the
+// algorithm and settings they were made with, so no extra columns are needed.
+
+fn argon2(config: &PasswordConfig) -> AppResult<Argon2<'static>> {
+    let params = Params::new(config.argon2_memory_kib, config.argon2_iterations, config.argon2_parallelism, None)
+        .map_err(|e| AppError::internal(format!("Invalid Argon2 settings: {}", e)))?;
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+/// Hashes a new password with the configured algorithm and settings.
+pub fn hash(config: &PasswordConfig, password: &str) -> AppResult<String> {
+    match config.algorithm {
+        HashAlgorithm::Bcrypt => bcrypt::hash(password, config.bcrypt_cost)
+            .map_err(|e| AppError::internal(format!("Failed to hash password: {}", e))),
+        HashAlgorithm::Argon2id => argon2(config)?
+            .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+            .map(|hash| hash.to_string())
+            .map_err(|e| AppError::internal(format!("Failed to hash password: {}", e))),
+    }
+}
+
+/// Checks `password` against a hash made by any supported algorithm.
+pub fn verify(password: &str, hash: &str) -> AppResult<bool> {
+    if hash.starts_with("$argon2") {
+        let parsed = PasswordHash::new(hash).map_err(|e| AppError::internal(format!("Failed to verify password: {}", e)))?;
+        // The hash's own settings are used, so old hashes keep working after the config changes
+        return Ok(Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok());
+    }
+    bcrypt::verify(password, hash).map_err(|e| AppError::internal(format!("Failed to verify password: {}", e)))
+}
+
+/// True when `hash` was made with another algorithm or other settings than
+/// the configured ones.
+pub fn needs_rehash(config: &PasswordConfig, hash: &str) -> bool {
+    match config.algorithm {
+        HashAlgorithm::Bcrypt => bcrypt_cost(hash) != Some(config.bcrypt_cost),
+        HashAlgorithm::Argon2id => {
+            let Ok(parsed) = PasswordHash::new(hash) else {
+                return true;
+            };
+            let Ok(params) = Params::try_from(&parsed) else {
+                return true;
+            };
+            parsed.algorithm.as_str() != "argon2id"
+                || parsed.version != Some(Version::V0x13.into())
+                || params.m_cost() != config.argon2_memory_kib
+                || params.t_cost() != config.argon2_iterations
+                || params.p_cost() != config.argon2_parallelism
+        }
+    }
+}
+
+// The cost of a "$2b$12$..." hash
+fn bcrypt_cost(hash: &str) -> Option<u32> {
+    let mut parts = hash.split('$');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(""), Some("2a" | "2b" | "2x" | "2y"), Some(cost)) => cost.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Replaces an outdated `stored_hash` after `password` was verified against
+/// it. Failing to upgrade is only logged, it never blocks the login.
+pub fn upgrade(conn: &mut PooledConn, config: &PasswordConfig, bn_number: &str, stored_hash: &str, password: &str) {
+    if !needs_rehash(config, stored_hash) {
+        return;
+    }
+
+    let result = hash(config, password).and_then(|new_hash| {
+        // Matching the old hash keeps a concurrent password change from being overwritten
+        conn.exec_drop(
+            "UPDATE users SET password = ? WHERE bn_number = ? AND password = ?",
+            (new_hash, bn_number, stored_hash),
+        )
+        .map_err(|e| AppError::database("Failed to store rehashed password", e))
+    });
+    match result {
+        Ok(()) => println!("Upgraded the password hash of {} to {:?}", bn_number, config.algorithm),
+        Err(e) => println!("Failed to upgrade the password hash of {}: {}", bn_number, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap settings so hashing in tests stays fast
+    fn argon2_config(memory_kib: u32, iterations: u32) -> PasswordConfig {
+        PasswordConfig {
+            algorithm: HashAlgorithm::Argon2id,
+            argon2_memory_kib: memory_kib,
+            argon2_iterations: iterations,
+            argon2_parallelism: 1,
+            ..PasswordConfig::default()
+        }
+    }
+
+    fn bcrypt_config(cost: u32) -> PasswordConfig {
+        PasswordConfig { algorithm: HashAlgorithm::Bcrypt, bcrypt_cost: cost, ..PasswordConfig::default() }
+    }
+
+    #[test]
+    fn reads_the_bcrypt_cost() {
+        for (hash, expected) in [
+            ("$2b$12$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW", Some(12)),
+            ("$2a$04$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW", Some(4)),
+            ("$2y$10$", Some(10)),
+            ("$2b$xx$R9h/cIPz0gi.URNNX3kh2O", None),
+            ("$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA", None),
+            ("2b$12$", None),
+            ("", None),
+        ] {
+            assert_eq!(bcrypt_cost(hash), expected, "{:?}", hash);
+        }
+    }
+
+    #[test]
+    fn rehashes_anything_made_with_other_settings() {
+        let current = argon2_config(64, 1);
+        let bcrypt_hash = hash(&bcrypt_config(4), "Secret123").unwrap();
+        let weaker_argon2_hash = hash(&argon2_config(32, 1), "Secret123").unwrap();
+        let current_argon2_hash = hash(&current, "Secret123").unwrap();
+
+        for (hash, expected) in [
+            (bcrypt_hash.as_str(), true),
+            (weaker_argon2_hash.as_str(), true),
+            (current_argon2_hash.as_str(), false),
+            ("not a hash", true),
+            ("$argon2id$v=19$m=64", true),
+        ] {
+            assert_eq!(needs_rehash(&current, hash), expected, "{:?}", hash);
+        }
+        assert!(verify("Secret123", &weaker_argon2_hash).unwrap());
+    }
+
+    #[test]
+    fn rehashes_bcrypt_hashes_of_another_cost() {
+        let bcrypt_hash = hash(&bcrypt_config(4), "Secret123").unwrap();
+
+        assert!(!needs_rehash(&bcrypt_config(4), &bcrypt_hash));
+        assert!(needs_rehash(&bcrypt_config(5), &bcrypt_hash));
+        assert!(needs_rehash(&bcrypt_config(4), &hash(&argon2_config(64, 1), "Secret123").unwrap()));
+        assert!(needs_rehash(&bcrypt_config(4), "not a hash"));
+    }
+}
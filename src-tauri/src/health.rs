@@ -0,0 +1,143 @@
+use std::sync::Mutex;
+
+use mysql::prelude::*;
+use mysql::Pool;
+use serde::Serialize;
+
+use crate::clock;
+use crate::config::GraphqlConfig;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Up,
+    Down,
+    Unknown,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DependencyStatus {
+    pub status: Status,
+    /// Why the dependency is down, or a short note on its last success.
+    pub detail: Option<String>,
+    /// Seconds since the Unix epoch of the last check.
+    pub checked_at: Option<u64>,
+}
+
+impl Default for DependencyStatus {
+    fn default() -> Self {
+        Self {
+            status: Status::Unknown,
+            detail: None,
+            checked_at: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SystemHealth {
+    pub healthy: bool,
+    pub database: DependencyStatus,
+    pub schema: DependencyStatus,
+    pub graphql: DependencyStatus,
+    pub last_sync: DependencyStatus,
+}
+
+impl SystemHealth {
+    /// The statuses alone, for callers without a session. Details name hosts
+    /// and endpoints and carry raw connection errors.
+    pub fn without_details(mut self) -> Self {
+        for dependency in [&mut self.database, &mut self.schema, &mut self.graphql, &mut self.last_sync] {
+            dependency.detail = None;
+        }
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Dependency {
+    Database,
+    Schema,
+    Graphql,
+    Sync,
+}
+
+/// Last known state of every external dependency, updated by startup, sync
+/// and `get_system_health` probes.
+#[derive(Default)]
+pub struct Health {
+    state: Mutex<SystemHealth>,
+}
+
+impl Health {
+    pub fn record(&self, dependency: Dependency, result: &Result<String, String>) {
+        let status = match result {
+            Ok(detail) => DependencyStatus {
+                status: Status::Up,
+                detail: Some(detail.clone()),
+                checked_at: Some(clock::now()),
+            },
+            Err(reason) => {
+                println!("{:?} is down: {}", dependency, reason);
+                DependencyStatus {
+                    status: Status::Down,
+                    detail: Some(reason.clone()),
+                    checked_at: Some(clock::now()),
+                }
+            }
+        };
+
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match dependency {
+            Dependency::Database => state.database = status,
+            Dependency::Schema => state.schema = status,
+            Dependency::Graphql => state.graphql = status,
+            Dependency::Sync => state.last_sync = status,
+        }
+    }
+
+    pub fn snapshot(&self) -> SystemHealth {
+        let mut health = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        health.healthy = [&health.database, &health.schema, &health.graphql, &health.last_sync]
+            .iter()
+            .all(|dependency| dependency.status == Status::Up);
+        health
+    }
+
+    pub fn is_up(&self, dependency: Dependency) -> bool {
+        let state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let status = match dependency {
+            Dependency::Database => &state.database,
+            Dependency::Schema => &state.schema,
+            Dependency::Graphql => &state.graphql,
+            Dependency::Sync => &state.last_sync,
+        };
+        status.status == Status::Up
+    }
+}
+
+pub fn probe_database(pool: &Pool) -> Result<String, String> {
+    let mut conn = pool.get_conn().map_err(|e| format!("Failed to get connection: {}", e))?;
+    let version: Option<String> = conn
+        .query_first("SELECT VERSION()")
+        .map_err(|e| format!("Failed to query database: {}", e))?;
+    Ok(format!("MySQL {}", version.unwrap_or_default()))
+}
+
+/// Sends the smallest valid GraphQL query so the check does not download data.
+pub async fn probe_graphql(graphql: &GraphqlConfig) -> Result<String, String> {
+    let request = surf::post(&graphql.endpoint)
+        .body_json(&serde_json::json!({ "query": "{ __typename }" }))
+        .map_err(|e| format!("Failed to build GraphQL request: {}", e))?;
+
+    let response = async_std::future::timeout(graphql.timeout(), request)
+        .await
+        .map_err(|_| format!("GraphQL endpoint timed out after {}s", graphql.timeout_secs))?
+        .map_err(|e| format!("GraphQL endpoint unreachable: {}", e))?;
+
+    if response.status().is_success() {
+        Ok(format!("{} reachable", graphql.endpoint))
+    } else {
+        Err(format!("GraphQL endpoint answered {}", response.status()))
+    }
+}
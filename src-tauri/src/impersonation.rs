@@ -0,0 +1,67 @@
+use mysql::PooledConn;
+
+use crate::audit;
+use crate::error::{AppError, AppResult};
+use crate::CurrentUser;
+
+/// Commands allowed besides reads while an admin views the app as someone else.
+const ALLOWED_COMMANDS: &[&str] = &["logout", "stop_impersonation"];
+
+/// Only commands that change nothing may run while viewing as another user.
+/// Reads are recognised by name, so a new write command is blocked by default.
+pub fn allows(command: &str) -> bool {
+    command.starts_with("get_") || command.starts_with("view_") || ALLOWED_COMMANDS.contains(&command)
+}
+
+/// The user to view the app as. Their pending password change or two-factor
+/// enrollment is left to them, so it does not get in the admin's way.
+pub fn load_target(conn: &mut PooledConn, bn_number: &str) -> AppResult<CurrentUser> {
+    let user = crate::load_user(conn, bn_number)?
+        .ok_or_else(|| AppError::not_found(format!("User {} not found", bn_number)))?;
+    Ok(CurrentUser {
+        must_change_password: false,
+        must_enroll_two_factor: false,
+        ..user
+    })
+}
+
+/// Audits the end of an impersonation; `ended_by` says why it ended.
+pub fn record_stop(conn: &mut PooledConn, machine: &str, admin: &CurrentUser, target: &CurrentUser, ended_by: &str) -> AppResult<()> {
+    audit::record(
+        conn,
+        &admin.bn_number,
+        machine,
+        "impersonation_stopped",
+        Some(&target.bn_number),
+        serde_json::json!({ "ended_by": ended_by }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_allows_reads_while_impersonating() {
+        for (command, allowed) in [
+            ("get_all_users", true),
+            ("get_current_user", true),
+            ("view_transaction", true),
+            ("logout", true),
+            ("stop_impersonation", true),
+            // Refreshing writes upstream data into MySQL
+            ("refresh_user_by_nim", false),
+            ("refresh_enrollment_by_nim", false),
+            ("update_user_role", false),
+            ("update_transaction_proctor", false),
+            ("allocate_exam", false),
+            ("start_impersonation", false),
+            ("trigger_sync", false),
+            // The prefix must start the name
+            ("forget_all_users", false),
+            ("reviewer_get_", false),
+        ] {
+            assert_eq!(allows(command), allowed, "{}", command);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use mysql::prelude::*;
+use mysql::PooledConn;
+use serde::Serialize;
+
+use crate::config::LoginConfig;
+use crate::error::{AppError, AppResult, ErrorCode};
+
+/// Name of this computer, recorded with every attempt so that one machine
+/// guessing across many accounts is slowed down as well.
+pub fn machine_name() -> String {
+    // Apps started from a desktop usually have no HOSTNAME, so ask the OS first
+    let from_os = hostname::get().ok().and_then(|name| name.into_string().ok());
+    from_os
+        .into_iter()
+        .chain(["COMPUTERNAME", "HOSTNAME"].iter().filter_map(|name| std::env::var(name).ok()))
+        .find(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LockedAccount {
+    pub username: String,
+    pub failed_attempts: u32,
+    /// Seconds since the Unix epoch.
+    pub last_failed_at: u64,
+    pub locked_until: u64,
+    pub machines: Vec<String>,
+}
+
+struct Failures {
+    count: u32,
+    last_failed_at: u64,
+    now: u64,
+}
+
+// Failures since the last successful login, ignoring those older than the lockout
+// window and those cleared by an admin. `column` is always a literal column name.
+fn failures(conn: &mut PooledConn, config: &LoginConfig, column: &str, value: &str) -> AppResult<Failures> {
+    let query = format!(
+        r"SELECT COUNT(*), UNIX_TIMESTAMP(MAX(attempted_at)), UNIX_TIMESTAMP()
+        FROM login_attempts
+        WHERE {column} = ? AND succeeded = FALSE AND cleared = FALSE
+            AND attempted_at > NOW() - INTERVAL ? MINUTE
+            AND id > COALESCE((SELECT MAX(id) FROM login_attempts WHERE {column} = ? AND succeeded = TRUE), 0)"
+    );
+    let row: Option<(u32, Option<u64>, u64)> = conn
+        .exec_first(query, (value, config.lockout_minutes, value))
+        .map_err(|e| AppError::database("Failed to count login attempts", e))?;
+
+    let (count, last_failed_at, now) = row.unwrap_or_default();
+    Ok(Failures {
+        count,
+        last_failed_at: last_failed_at.unwrap_or(now),
+        now,
+    })
+}
+
+/// Wait required after `count` consecutive failures: the base delay doubled
+/// for every failure after the first, capped at `max_delay_secs`.
+fn delay_secs(config: &LoginConfig, count: u32) -> u64 {
+    if count == 0 {
+        return 0;
+    }
+    let factor = 1u64 << (count - 1).min(32);
+    config.base_delay_secs.saturating_mul(factor).min(config.max_delay_secs)
+}
+
+fn locked(config: &LoginConfig, username: &str, failures: &Failures) -> AppError {
+    let locked_until = failures.last_failed_at + u64::from(config.lockout_minutes) * 60;
+    let retry_after_secs = locked_until.saturating_sub(failures.now);
+
+    AppError::new(
+        ErrorCode::AccountLocked,
+        format!(
+            "Account {} is locked after {} failed attempts; try again in {} minute(s)",
+            username,
+            failures.count,
+            retry_after_secs.div_ceil(60)
+        ),
+    )
+    .with_details(serde_json::json!({
+        "username": username,
+        "locked_until": locked_until,
+        "retry_after_secs": retry_after_secs,
+    }))
+}
+
+/// Rejects the attempt when the account is locked or when the account or this
+/// machine has not waited long enough since its last failure.
+pub fn check(conn: &mut PooledConn, config: &LoginConfig, username: &str, machine: &str) -> AppResult<()> {
+    let account = failures(conn, config, "username", username)?;
+    if account.count >= config.max_failures {
+        return Err(locked(config, username, &account));
+    }
+
+    let machine_failures = failures(conn, config, "machine", machine)?;
+    for failures in [&account, &machine_failures] {
+        let retry_after_secs = (failures.last_failed_at + delay_secs(config, failures.count)).saturating_sub(failures.now);
+        if retry_after_secs > 0 {
+            return Err(AppError::new(
+                ErrorCode::TooManyAttempts,
+                format!("Too many failed login attempts; wait {} second(s) before trying again", retry_after_secs),
+            )
+            .with_details(serde_json::json!({ "retry_after_secs": retry_after_secs })));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn record(conn: &mut PooledConn, username: &str, machine: &str, succeeded: bool) -> AppResult<()> {
+    conn.exec_drop(
+        "INSERT INTO login_attempts (username, machine, succeeded) VALUES (?, ?, ?)",
+        (username, machine, succeeded),
+    )
+    .map_err(|e| AppError::database("Failed to record login attempt", e))
+}
+
+/// Records a wrong password or two-factor code and returns the error to report:
+/// the lockout once this failure reaches the limit, otherwise `message` with
+/// the attempts left.
+pub fn record_failure(
+    conn: &mut PooledConn,
+    config: &LoginConfig,
+    username: &str,
+    machine: &str,
+    message: &str,
+) -> AppResult<AppError> {
+    record(conn, username, machine, false)?;
+
+    let account = failures(conn, config, "username", username)?;
+    if account.count >= config.max_failures {
+        println!("Locked account {} after {} failed attempts", username, account.count);
+        return Ok(locked(config, username, &account));
+    }
+
+    Ok(AppError::new(ErrorCode::InvalidCredentials, message)
+        .with_details(serde_json::json!({ "remaining_attempts": config.max_failures - account.count })))
+}
+
+pub fn locked_accounts(conn: &mut PooledConn, config: &LoginConfig) -> AppResult<Vec<LockedAccount>> {
+    conn.exec_map(
+        r"SELECT f.username, COUNT(*), UNIX_TIMESTAMP(MAX(f.attempted_at)), GROUP_CONCAT(DISTINCT f.machine ORDER BY f.machine)
+        FROM login_attempts f
+        WHERE f.succeeded = FALSE AND f.cleared = FALSE
+            AND f.attempted_at > NOW() - INTERVAL ? MINUTE
+            AND f.id > COALESCE((SELECT MAX(s.id) FROM login_attempts s WHERE s.username = f.username AND s.succeeded = TRUE), 0)
+        GROUP BY f.username
+        HAVING COUNT(*) >= ?
+        ORDER BY MAX(f.attempted_at) DESC",
+        (config.lockout_minutes, config.max_failures),
+        |(username, failed_attempts, last_failed_at, machines): (String, u32, u64, String)| LockedAccount {
+            username,
+            failed_attempts,
+            last_failed_at,
+            locked_until: last_failed_at + u64::from(config.lockout_minutes) * 60,
+            machines: machines.split(',').map(String::from).collect(),
+        },
+    )
+    .map_err(|e| AppError::database("Failed to query locked accounts", e))
+}
+
+/// Lifts a lockout by marking the account's failures as cleared. The rows are
+/// kept so the attempts stay visible in `login_attempts`.
+pub fn clear(conn: &mut PooledConn, username: &str) -> AppResult<()> {
+    conn.exec_drop(
+        "UPDATE login_attempts SET cleared = TRUE WHERE username = ? AND succeeded = FALSE AND cleared = FALSE",
+        (username,),
+    )
+    .map_err(|e| AppError::database("Failed to clear login attempts", e))?;
+
+    if conn.affected_rows() == 0 {
+        return Err(AppError::not_found(format!("No failed login attempts recorded for {}", username)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles_the_delay_for_each_failure_up_to_the_cap() {
+        let config = LoginConfig { base_delay_secs: 1, max_delay_secs: 30, ..LoginConfig::default() };
+        for (count, expected) in [(0, 0), (1, 1), (2, 2), (3, 4), (4, 8), (5, 16), (6, 30), (7, 30), (40, 30), (u32::MAX, 30)] {
+            assert_eq!(delay_secs(&config, count), expected, "{} failures", count);
+        }
+    }
+
+    #[test]
+    fn scales_with_the_base_delay() {
+        let config = LoginConfig { base_delay_secs: 5, max_delay_secs: 60, ..LoginConfig::default() };
+        for (count, expected) in [(0, 0), (1, 5), (2, 10), (3, 20), (4, 40), (5, 60), (6, 60)] {
+            assert_eq!(delay_secs(&config, count), expected, "{} failures", count);
+        }
+    }
+
+    #[test]
+    fn never_overflows() {
+        let config = LoginConfig { base_delay_secs: u64::MAX / 2, max_delay_secs: u64::MAX, ..LoginConfig::default() };
+        assert_eq!(delay_secs(&config, 3), u64::MAX);
+        assert_eq!(delay_secs(&config, u32::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn disables_the_delay_with_a_zero_base() {
+        let config = LoginConfig { base_delay_secs: 0, ..LoginConfig::default() };
+        for count in [0, 1, 5, u32::MAX] {
+            assert_eq!(delay_secs(&config, count), 0, "{} failures", count);
+        }
+    }
+}
@@ -1,14 +1,42 @@
-use bcrypt::{verify, hash, DEFAULT_COST}; // Ensure bcrypt functions are imported
 use cynic::http::SurfExt;
 use cynic::QueryBuilder;
 use serde::{Deserialize, Serialize};
-use std::sync::Mutex;
 use mysql::{prelude::*, TxOpts};
 use mysql::{PooledConn, params};
 use mysql::Pool;
-use tauri::State;
+use std::time::Duration;
+use tauri::{Manager, State};
 use async_std::task;
-use rand::Rng;
+
+mod allocation;
+mod audit;
+mod clock;
+mod codes;
+mod config;
+mod error;
+mod hashing;
+mod health;
+mod impersonation;
+mod lockout;
+mod migrations;
+mod password_policy;
+mod password_reset;
+mod permissions;
+mod remember;
+mod sessions;
+mod suspension;
+mod sync;
+mod totp;
+mod two_factor;
+
+use allocation::{ExamRequest, RoomAllocation};
+use codes::{CodeFormat, MySqlSequence};
+use config::{AppConfig, DatabaseConfig, GraphqlConfig, LoginConfig, PasswordConfig, SyncConfig};
+use error::{AppError, AppResult, ErrorCode};
+use health::{Dependency, Health, SystemHealth};
+use permissions::{authenticate, authorize, require_account_ready, Permissions};
+use remember::RememberMe;
+use sessions::{Session, SessionStore};
 
 #[cynic::schema("sr-exam")]
 mod schema {}
@@ -19,7 +47,7 @@ pub struct UsersQuery {
     pub get_all_user: Vec<User>,
 }
 
-#[derive(cynic::QueryFragment, Debug, Serialize)]
+#[derive(cynic::QueryFragment, Clone, Debug, Serialize)]
 #[cynic(graphql_type = "User")]
 pub struct User {
     #[cynic(rename = "bn_number")]
@@ -37,7 +65,7 @@ pub struct AllSubjectsQuery {
     pub get_all_subject: Vec<Subject>,
 }
 
-#[derive(cynic::QueryFragment, Debug, Serialize)]
+#[derive(cynic::QueryFragment, Clone, Debug, Serialize)]
 pub struct Subject {
     #[cynic(rename = "subject_name")]
     pub subject_name: String,
@@ -51,7 +79,7 @@ pub struct AllRoomsQuery {
     pub get_all_room: Vec<Room>,
 }
 
-#[derive(cynic::QueryFragment, Debug, Serialize)]
+#[derive(cynic::QueryFragment, Clone, Debug, Serialize)]
 pub struct Room {
     pub campus: String,
     #[cynic(rename = "room_capacity")]
@@ -66,7 +94,7 @@ pub struct AllEnrollmentQuery {
     pub get_all_enrollment: Option<Vec<Option<Enrollment>>>,
 }
 
-#[derive(cynic::QueryFragment, Debug, Serialize)]
+#[derive(cynic::QueryFragment, Clone, Debug, Serialize)]
 pub struct Enrollment {
     #[cynic(rename = "class_code")]
     pub class_code: String,
@@ -75,6 +103,75 @@ pub struct Enrollment {
     pub subject_code: String,
 }
 
+#[derive(cynic::QueryVariables, Debug)]
+pub struct NimArguments {
+    pub nim: String,
+}
+
+#[derive(cynic::QueryVariables, Debug)]
+pub struct InitialArguments {
+    pub initial: String,
+}
+
+#[derive(cynic::QueryVariables, Debug)]
+pub struct RoleArguments {
+    pub role: String,
+}
+
+#[derive(cynic::QueryVariables, Debug)]
+pub struct CampusArguments {
+    pub campus: String,
+}
+
+#[derive(cynic::QueryVariables, Debug)]
+pub struct SubjectCodeArguments {
+    pub subject_code: String,
+}
+
+#[derive(cynic::QueryFragment, Debug)]
+#[cynic(graphql_type = "Query", variables = "NimArguments")]
+pub struct UserByNimQuery {
+    #[cynic(rename = "getUserByNIM")]
+    #[arguments(nim: $nim)]
+    pub get_user_by_nim: User,
+}
+
+#[derive(cynic::QueryFragment, Debug)]
+#[cynic(graphql_type = "Query", variables = "InitialArguments")]
+pub struct UserByInitialQuery {
+    #[arguments(initial: $initial)]
+    pub get_user_by_initial: User,
+}
+
+#[derive(cynic::QueryFragment, Debug)]
+#[cynic(graphql_type = "Query", variables = "RoleArguments")]
+pub struct UsersWithRoleQuery {
+    #[arguments(role: $role)]
+    pub get_user_with_role: Vec<User>,
+}
+
+#[derive(cynic::QueryFragment, Debug)]
+#[cynic(graphql_type = "Query", variables = "NimArguments")]
+pub struct EnrollmentByNimQuery {
+    #[cynic(rename = "getEnrollmentByNIM")]
+    #[arguments(nim: $nim)]
+    pub get_enrollment_by_nim: Option<Vec<Enrollment>>,
+}
+
+#[derive(cynic::QueryFragment, Debug)]
+#[cynic(graphql_type = "Query", variables = "CampusArguments")]
+pub struct RoomsByCampusQuery {
+    #[arguments(campus: $campus)]
+    pub get_room_by_campus: Vec<Room>,
+}
+
+#[derive(cynic::QueryFragment, Debug)]
+#[cynic(graphql_type = "Query", variables = "SubjectCodeArguments")]
+pub struct SubjectByCodeQuery {
+    #[arguments(subject_code: $subject_code)]
+    pub get_subject_by_code: Subject,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct CurrentUser {
     bn_number: String,
@@ -84,83 +181,217 @@ struct CurrentUser {
     role: String,
     initial: Option<String>,
     login_type: String, // Added field
+    /// Logged in with the NIM or initial as password; only `change_password` is allowed until it is replaced.
+    must_change_password: bool,
+    /// The role requires two-factor but the user has not enrolled yet.
+    must_enroll_two_factor: bool,
+    /// bn_number of the admin viewing the app as this user.
+    #[serde(default)]
+    impersonated_by: Option<String>,
 }
 
+const SESSION_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
 struct AppState {
-    user: Mutex<Option<CurrentUser>>,
+    sessions: SessionStore,
     mysql_pool: Pool,
+    permissions: Permissions,
+    transaction_code_format: CodeFormat,
+    graphql: GraphqlConfig,
+    health: Health,
+    login: LoginConfig,
+    machine: String,
+    password_policy: PasswordConfig,
+    pending_logins: two_factor::PendingLogins,
+    sync: SyncConfig,
+    sync_lock: sync::SyncLock,
 }
 
-struct MySQLConfig {
-    user: String,
-    password: String,
-    host: String,
-    database: String,
+impl AppState {
+    fn conn(&self) -> AppResult<PooledConn> {
+        self.mysql_pool.get_conn().map_err(|e| AppError::database("Failed to get connection", e))
+    }
 }
 
-impl MySQLConfig {
-    fn new(user: String, password: String, host: String, database: String) -> Self {
-        Self {
-            user,
-            password,
-            host,
-            database,
-        }
-    }
+#[tauri::command]
+fn get_current_user(state: State<'_, AppState>, token: Option<String>) -> AppResult<Option<CurrentUser>> {
+    token.map(|token| authenticate(&state, &token)).transpose()
+}
 
-    fn format_url(&self) -> String {
-        format!(
-            "mysql://{}:{}@{}/{}",
-            self.user, self.password, self.host, self.database
-        )
+// Audits an impersonation that ended with its session
+fn end_impersonation(state: &AppState, session: &Session, ended_by: &str) {
+    let Some(admin) = &session.impersonator else {
+        return;
+    };
+    let result = state
+        .conn()
+        .and_then(|mut conn| impersonation::record_stop(&mut conn, &state.machine, admin, &session.user, ended_by));
+    if let Err(e) = result {
+        println!("Failed to audit the end of {} viewing as {}: {}", admin.bn_number, session.user.bn_number, e);
     }
 }
 
 #[tauri::command]
-fn get_current_user(state: State<'_, AppState>) -> Option<CurrentUser> {
-    state.user.lock().unwrap().clone()
+fn logout(state: State<'_, AppState>, remember: State<'_, RememberMe>, token: String) -> AppResult<()> {
+    remember.forget(Some(&token));
+    if let Some(session) = state.sessions.remove(&token)? {
+        end_impersonation(&state, &session, "logout");
+        println!("{} logged out", session.user.bn_number);
+    }
+    Ok(())
 }
 
-#[tauri::command]
-fn logout(state: State<'_, AppState>) {
-    let mut user = state.user.lock().unwrap();
-    *user = None;
+async fn run_graphql<ResponseData, Vars>(
+    graphql: &GraphqlConfig,
+    operation: cynic::Operation<ResponseData, Vars>,
+) -> AppResult<ResponseData>
+where
+    Vars: Serialize,
+    ResponseData: serde::de::DeserializeOwned + 'static,
+{
+    let request = surf::post(&graphql.endpoint).run_graphql(operation);
+
+    let response = async_std::future::timeout(graphql.timeout(), request)
+        .await
+        .map_err(|_| AppError::network(format!("GraphQL request timed out after {}s", graphql.timeout_secs)))?
+        .map_err(|e| AppError::network(format!("GraphQL request failed: {}", e)))?;
+
+    match response.data {
+        Some(data) => Ok(data),
+        None => {
+            let errors: Vec<String> = response.errors.unwrap_or_default().into_iter().map(|e| e.message).collect();
+            Err(AppError::network(format!("GraphQL response had no data: {}", errors.join("; "))))
+        }
+    }
 }
 
-#[tauri::command]
-async fn get_all_users() -> Result<Vec<User>, ()> {
-    let operation = UsersQuery::build({});
-    let response = surf::post("https://academic-slc.apps.binus.ac.id/tpa-241/query")
-        .run_graphql(operation)
-        .await;
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum DataSource {
+    /// The copy in MySQL, as of the last sync.
+    Local,
+    /// Fetched from the GraphQL API just now.
+    Remote,
+}
+
+/// Rows of a synced table and how fresh they are.
+#[derive(Clone, Debug, Serialize)]
+struct Dataset<T> {
+    rows: Vec<T>,
+    source: DataSource,
+    /// Seconds since the Unix epoch of the last successful sync, if any.
+    synced_at: Option<u64>,
+    /// Seconds since then; 0 for remote rows.
+    age_secs: Option<u64>,
+}
 
-    match response{
-        Ok(response) => {
-            let data = response.data.unwrap();
-            Ok(data.get_all_user)
+// Serves the rows synced into MySQL unless `force_remote` asks for the GraphQL
+// API. Falls back to the API when MySQL cannot be reached.
+async fn load_dataset<T>(
+    state: &AppState,
+    what: &str,
+    force_remote: Option<bool>,
+    remote: impl std::future::Future<Output = AppResult<Vec<T>>>,
+    local: impl FnOnce(&Pool) -> AppResult<Vec<T>>,
+) -> AppResult<Dataset<T>> {
+    if !force_remote.unwrap_or(false) {
+        let local = state.conn().and_then(|mut conn| {
+            let freshness = sync::last_synced(&mut conn)?;
+            Ok((local(&state.mysql_pool)?, freshness))
+        });
+        match local {
+            Ok((rows, (synced_at, age_secs))) => {
+                return Ok(Dataset { rows, source: DataSource::Local, synced_at, age_secs });
+            }
+            Err(e) if e.code == ErrorCode::DatabaseUnavailable => {
+                println!("Fetching {} from GraphQL: {}", what, e);
+            }
+            Err(e) => return Err(e),
         }
-        Err(_err) => Err(()),
     }
+
+    let result = remote.await;
+    state.health.record(
+        Dependency::Graphql,
+        &result.as_ref().map(|rows| format!("Fetched {} {}", rows.len(), what)).map_err(|e| e.message.clone()),
+    );
+    Ok(Dataset {
+        rows: result?,
+        source: DataSource::Remote,
+        synced_at: Some(clock::now()),
+        age_secs: Some(0),
+    })
+}
+
+async fn fetch_users(graphql: &GraphqlConfig) -> AppResult<Vec<User>> {
+    let operation = UsersQuery::build(());
+    Ok(run_graphql(graphql, operation).await?.get_all_user)
+}
+
+fn local_users(pool: &Pool) -> AppResult<Vec<User>> {
+    let mut conn = pool.get_conn().map_err(|e| AppError::database("Failed to get connection", e))?;
+
+    conn.query_map(
+        "SELECT bn_number, nim, name, major, role, initial FROM users",
+        |(bn_number, nim, name, major, role, initial): (String, String, String, Option<String>, Option<String>, Option<String>)| User {
+            bn_number: cynic::Id::new(bn_number),
+            nim,
+            name,
+            major: major.unwrap_or_default(),
+            role: role.unwrap_or_default(),
+            // sync stores a missing initial as an empty string
+            initial: initial.filter(|initial| !initial.is_empty()),
+        },
+    ).map_err(|e| AppError::database("Failed to query users", e))
 }
 
 #[tauri::command]
-async fn get_all_subject() -> Result<Vec<Subject>, ()> {
+async fn get_all_users(state: State<'_, AppState>, token: String, force_remote: Option<bool>) -> AppResult<Dataset<User>> {
+    authenticate(&state, &token)?;
+
+    load_dataset(&state, "users", force_remote, fetch_users(&state.graphql), local_users).await
+}
+
+async fn fetch_subjects(graphql: &GraphqlConfig) -> AppResult<Vec<Subject>> {
     let operation = AllSubjectsQuery::build(());
-    let response = surf::post("https://academic-slc.apps.binus.ac.id/tpa-241/query")
-    .run_graphql(operation)
-    .await
-    .unwrap();
-    return Ok(response.data.unwrap().get_all_subject);
+    Ok(run_graphql(graphql, operation).await?.get_all_subject)
+}
+
+fn local_subjects(pool: &Pool) -> AppResult<Vec<Subject>> {
+    let mut conn = pool.get_conn().map_err(|e| AppError::database("Failed to get connection", e))?;
+
+    conn.query_map(
+        "SELECT subject_code, subject_name FROM subjects",
+        |(subject_code, subject_name)| Subject { subject_code, subject_name },
+    ).map_err(|e| AppError::database("Failed to query subjects", e))
 }
 
 #[tauri::command]
-async fn get_all_room() -> Result<Vec<Room>, ()> {
+async fn get_all_subject(state: State<'_, AppState>, token: String, force_remote: Option<bool>) -> AppResult<Dataset<Subject>> {
+    authenticate(&state, &token)?;
+
+    load_dataset(&state, "subjects", force_remote, fetch_subjects(&state.graphql), local_subjects).await
+}
+
+async fn fetch_rooms(graphql: &GraphqlConfig) -> AppResult<Vec<Room>> {
     let operation = AllRoomsQuery::build(());
-    let response = surf::post("https://academic-slc.apps.binus.ac.id/tpa-241/query")
-    .run_graphql(operation)
-    .await
-    .unwrap();
-    return Ok(response.data.unwrap().get_all_room);
+    Ok(run_graphql(graphql, operation).await?.get_all_room)
+}
+
+fn local_rooms(pool: &Pool) -> AppResult<Vec<Room>> {
+    let mut conn = pool.get_conn().map_err(|e| AppError::database("Failed to get connection", e))?;
+
+    conn.query_map(
+        "SELECT room_number, room_capacity, campus FROM rooms",
+        |(room_number, room_capacity, campus)| Room { room_number, room_capacity, campus },
+    ).map_err(|e| AppError::database("Failed to query rooms", e))
+}
+
+#[tauri::command]
+async fn get_all_room(state: State<'_, AppState>, token: String, force_remote: Option<bool>) -> AppResult<Dataset<Room>> {
+    authenticate(&state, &token)?;
+
+    load_dataset(&state, "rooms", force_remote, fetch_rooms(&state.graphql), local_rooms).await
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -170,17 +401,20 @@ struct ScheduledRoom {
 }
 
 #[tauri::command]
-async fn get_scheduled_rooms(state: State<'_, AppState>, selected_date: String) -> Result<Vec<ScheduledRoom>, String> {
+async fn get_scheduled_rooms(state: State<'_, AppState>, token: String, selected_date: String) -> AppResult<Vec<ScheduledRoom>> {
+    authenticate(&state, &token)?;
+
     println!("Selected Date: {}", selected_date);
-    let mut conn = state.mysql_pool.get_conn().map_err(|e| format!("Failed to get connection: {}", e))?;
+    let mut conn = state.conn()?;
 
-    let query = format!("SELECT room_number, shift_code FROM transaction_header WHERE date = '{}'", selected_date);
+    let query = "SELECT room_number, shift_code FROM transaction_header WHERE date = ?";
     println!("SQL Query: {}", query); // Add debug print for SQL query
     
-    let schedules = conn.query_map(
+    let schedules = conn.exec_map(
         query,
+        (selected_date,),
         |(room_number, shift_code)| ScheduledRoom { room_number, shift_code }
-    ).map_err(|e| format!("Failed to query scheduled rooms: {}", e))?;
+    ).map_err(|e| AppError::database("Failed to query scheduled rooms", e))?;
 
     println!("Scheduled Rooms: {:?}", schedules); // Add debug print for fetched scheduled rooms
 
@@ -195,36 +429,146 @@ struct Shift {
 }
 
 #[tauri::command]
-async fn get_all_shifts(state: State<'_, AppState>) -> Result<Vec<Shift>, String> {
-    let mut conn = state.mysql_pool.get_conn().map_err(|e| format!("Failed to get connection: {}", e))?;
+async fn get_all_shifts(state: State<'_, AppState>, token: String) -> AppResult<Vec<Shift>> {
+    authenticate(&state, &token)?;
+
+    let mut conn = state.conn()?;
 
     let query = "SELECT shift_code, start_time, end_time FROM shifts";
     
     let shifts = conn.query_map(
         query,
         |(shift_code, start_time, end_time)| Shift { shift_code, start_time, end_time }
-    ).map_err(|e| format!("Failed to query shifts: {}", e))?;
+    ).map_err(|e| AppError::database("Failed to query shifts", e))?;
 
     Ok(shifts)
 }
 
-#[tauri::command]
-async fn get_all_enrollment() -> Result<Vec<Enrollment>, ()> {
+async fn fetch_enrollments(graphql: &GraphqlConfig) -> AppResult<Vec<Enrollment>> {
     let operation = AllEnrollmentQuery::build(());
-    let response = surf::post("https://academic-slc.apps.binus.ac.id/tpa-241/query")
-        .run_graphql(operation)
-        .await;
-
-    match response {
-        Ok(response) => {
-            let data = response.data.unwrap();
-            match data.get_all_enrollment {
-                Some(enrollments) => Ok(enrollments.into_iter().filter_map(|e| e).collect()),
-                None => Ok(vec![]),
-            }
-        }
-        Err(_err) => Err(()),
+    let enrollments = run_graphql(graphql, operation).await?.get_all_enrollment;
+
+    Ok(enrollments.unwrap_or_default().into_iter().flatten().collect())
+}
+
+fn local_enrollments(pool: &Pool) -> AppResult<Vec<Enrollment>> {
+    let mut conn = pool.get_conn().map_err(|e| AppError::database("Failed to get connection", e))?;
+
+    conn.query_map(
+        "SELECT class_code, nim, subject_code FROM enrollments",
+        |(class_code, nim, subject_code)| Enrollment { class_code, nim, subject_code },
+    ).map_err(|e| AppError::database("Failed to query enrollments", e))
+}
+
+#[tauri::command]
+async fn get_all_enrollment(state: State<'_, AppState>, token: String, force_remote: Option<bool>) -> AppResult<Dataset<Enrollment>> {
+    authenticate(&state, &token)?;
+
+    load_dataset(&state, "enrollments", force_remote, fetch_enrollments(&state.graphql), local_enrollments).await
+}
+
+// Fetches rows for a targeted lookup and stores them with `refresh`, so a
+// single user or student's enrollments can be updated without a full sync.
+// `None` from upstream means it has nothing for the lookup, and nothing is
+// written then.
+async fn look_up<T: Clone>(
+    state: &AppState,
+    what: &str,
+    remote: impl std::future::Future<Output = AppResult<Option<Vec<T>>>>,
+    refresh: impl FnOnce(&mut PooledConn, &SyncConfig, Vec<T>) -> AppResult<sync::TableReport>,
+) -> AppResult<Vec<T>> {
+    let result = remote.await;
+    state.health.record(
+        Dependency::Graphql,
+        &result
+            .as_ref()
+            .map(|rows| format!("Looked up {} {}", rows.as_ref().map_or(0, Vec::len), what))
+            .map_err(|e| e.message.clone()),
+    );
+    let rows = result?.ok_or_else(|| AppError::not_found(format!("No {} found upstream", what)))?;
+
+    let report = refresh(&mut state.conn()?, &state.sync, rows.clone())?;
+    println!(
+        "Refreshed {}: {} inserted, {} updated, {} deleted, {} skipped",
+        what, report.inserted, report.updated, report.deleted, report.skipped
+    );
+    Ok(rows)
+}
+
+fn single<T>(rows: Vec<T>, what: String) -> AppResult<T> {
+    rows.into_iter().next().ok_or_else(|| AppError::not_found(format!("{} not found", what)))
+}
+
+// Students may only refresh their own records
+fn require_own_nim(user: &CurrentUser, command: &str, nim: &str) -> AppResult<()> {
+    if user.role == "Student" && user.nim != nim {
+        println!("Forbidden: {} tried to run {} for {}", user.bn_number, command, nim);
+        return Err(AppError::new(
+            ErrorCode::Forbidden,
+            format!("Forbidden: students may only run {} for their own NIM", command),
+        )
+        .with_details(serde_json::json!({ "command": command, "nim": nim })));
     }
+    Ok(())
+}
+
+#[tauri::command]
+async fn refresh_user_by_nim(state: State<'_, AppState>, token: String, nim: String) -> AppResult<User> {
+    let user = authorize(&state, &token, "refresh_user_by_nim")?;
+    require_own_nim(&user, "refresh_user_by_nim", &nim)?;
+
+    let operation = UserByNimQuery::build(NimArguments { nim: nim.clone() });
+    let remote = async { Ok(Some(vec![run_graphql(&state.graphql, operation).await?.get_user_by_nim])) };
+    single(look_up(&state, "users", remote, sync::refresh_users).await?, format!("User {}", nim))
+}
+
+#[tauri::command]
+async fn refresh_user_by_initial(state: State<'_, AppState>, token: String, initial: String) -> AppResult<User> {
+    authorize(&state, &token, "refresh_user_by_initial")?;
+
+    let operation = UserByInitialQuery::build(InitialArguments { initial: initial.clone() });
+    let remote = async { Ok(Some(vec![run_graphql(&state.graphql, operation).await?.get_user_by_initial])) };
+    single(look_up(&state, "users", remote, sync::refresh_users).await?, format!("User {}", initial))
+}
+
+#[tauri::command]
+async fn refresh_user_with_role(state: State<'_, AppState>, token: String, role: String) -> AppResult<Vec<User>> {
+    authorize(&state, &token, "refresh_user_with_role")?;
+
+    let operation = UsersWithRoleQuery::build(RoleArguments { role });
+    let remote = async { Ok(Some(run_graphql(&state.graphql, operation).await?.get_user_with_role)) };
+    look_up(&state, "users", remote, sync::refresh_users).await
+}
+
+#[tauri::command]
+async fn refresh_enrollment_by_nim(state: State<'_, AppState>, token: String, nim: String) -> AppResult<Vec<Enrollment>> {
+    let user = authorize(&state, &token, "refresh_enrollment_by_nim")?;
+    require_own_nim(&user, "refresh_enrollment_by_nim", &nim)?;
+
+    let operation = EnrollmentByNimQuery::build(NimArguments { nim: nim.clone() });
+    let remote = async { Ok(run_graphql(&state.graphql, operation).await?.get_enrollment_by_nim) };
+    look_up(&state, "enrollments", remote, |conn, config, enrollments| {
+        sync::refresh_enrollments(conn, config, &nim, enrollments)
+    })
+    .await
+}
+
+#[tauri::command]
+async fn refresh_room_by_campus(state: State<'_, AppState>, token: String, campus: String) -> AppResult<Vec<Room>> {
+    authorize(&state, &token, "refresh_room_by_campus")?;
+
+    let operation = RoomsByCampusQuery::build(CampusArguments { campus });
+    let remote = async { Ok(Some(run_graphql(&state.graphql, operation).await?.get_room_by_campus)) };
+    look_up(&state, "rooms", remote, sync::refresh_rooms).await
+}
+
+#[tauri::command]
+async fn refresh_subject_by_code(state: State<'_, AppState>, token: String, subject_code: String) -> AppResult<Subject> {
+    authorize(&state, &token, "refresh_subject_by_code")?;
+
+    let operation = SubjectByCodeQuery::build(SubjectCodeArguments { subject_code: subject_code.clone() });
+    let remote = async { Ok(Some(vec![run_graphql(&state.graphql, operation).await?.get_subject_by_code])) };
+    single(look_up(&state, "subjects", remote, sync::refresh_subjects).await?, format!("Subject {}", subject_code))
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -238,8 +582,10 @@ struct ViewTransaction {
 }
 
 #[tauri::command]
-async fn view_transaction(state: State<'_, AppState>) -> Result<Vec<ViewTransaction>, String> {
-    let mut conn = state.mysql_pool.get_conn().map_err(|e| format!("Failed to get connection: {}", e))?;
+async fn view_transaction(state: State<'_, AppState>, token: String) -> AppResult<Vec<ViewTransaction>> {
+    authenticate(&state, &token)?;
+
+    let mut conn = state.conn()?;
 
     let query = "SELECT transaction_code, subject_code, shift_code, room_number, date, proctor FROM transaction_header";
     
@@ -255,7 +601,7 @@ async fn view_transaction(state: State<'_, AppState>) -> Result<Vec<ViewTransact
                 proctor,
             }
         },
-    ).map_err(|e| format!("Failed to query transaction headers: {}", e))?;
+    ).map_err(|e| AppError::database("Failed to query transaction headers", e))?;
 
     Ok(transaction_headers)
 }
@@ -263,61 +609,87 @@ async fn view_transaction(state: State<'_, AppState>) -> Result<Vec<ViewTransact
 #[tauri::command]
 async fn update_transaction_proctor(
     state: State<'_, AppState>,
+    token: String,
     transaction_code: String,
     selected_assistant: String
-) -> Result<String, String> {
+) -> AppResult<String> {
+    authorize(&state, &token, "update_transaction_proctor")?;
+
     // Log the input parameters
     println!("Received request to update transaction proctor.");
     println!("Transaction code: {}", transaction_code);
     println!("Selected assistant: {}", selected_assistant);
 
     // Get a connection from the MySQL pool
-    let mut conn = match state.mysql_pool.get_conn() {
-        Ok(conn) => {
-            println!("Successfully obtained connection from pool.");
-            conn
-        },
-        Err(e) => {
-            println!("Failed to get connection: {}", e);
-            return Err(format!("Failed to get connection: {}", e));
-        }
-    };
+    let mut conn = state.conn()?;
+    println!("Successfully obtained connection from pool.");
 
     // Prepare the SQL query to update the proctor
     let query = "UPDATE transaction_header SET proctor = :selected_assistant WHERE transaction_code = :transaction_code";
     println!("SQL query prepared: {}", query);
 
+    let exists: Option<String> = conn.exec_first(
+        "SELECT transaction_code FROM transaction_header WHERE transaction_code = ?",
+        (&transaction_code,),
+    ).map_err(|e| AppError::database("Failed to query transaction", e))?;
+    if exists.is_none() {
+        return Err(AppError::not_found(format!("Transaction {} does not exist", transaction_code)));
+    }
+
     // Execute the SQL query
-    match conn.exec_drop(query, params! {
+    conn.exec_drop(query, params! {
         "selected_assistant" => selected_assistant.clone(),
         "transaction_code" => transaction_code.clone()
-    }) {
-        Ok(_) => {
-            println!("Successfully updated proctor for transaction_code: {}", transaction_code);
-            Ok("Proctor updated successfully".to_string())
-        },
-        Err(e) => {
-            println!("Failed to update transaction: {}", e);
-            Err(format!("Failed to update transaction: {}", e))
-        }
-    }
+    }).map_err(|e| AppError::database("Failed to update transaction", e))?;
+
+    println!("Successfully updated proctor for transaction_code: {}", transaction_code);
+    Ok("Proctor updated successfully".to_string())
 }
 
 #[derive(Serialize)]
 struct AllocateExamResponse {
     transaction_code: String,
+    exam_code: String,
+    rooms: Vec<RoomAllocation>,
     message: String,
 }
 
+fn book_exam(
+    state: &AppState,
+    request: &ExamRequest,
+    room_numbers: &[String],
+) -> AppResult<AllocateExamResponse> {
+    let mut conn = state.conn()?;
+    
+    let mut transaction = conn.start_transaction(TxOpts::default()).map_err(|e| AppError::database("Failed to start transaction", e))?;
+
+    let mut sequence = MySqlSequence::new(state.mysql_pool.clone());
+    let rooms = allocation::allocate(&mut transaction, request, room_numbers, &state.transaction_code_format, &mut sequence)?;
+    
+    transaction.commit().map_err(|e| AppError::database("Failed to commit transaction", e))?;
+    
+    println!("Transaction committed successfully.");
+
+    Ok(AllocateExamResponse {
+        transaction_code: rooms[0].transaction_code.clone(),
+        exam_code: rooms[0].transaction_code.clone(),
+        message: format!("Exam allocated successfully in {} room(s)", rooms.len()),
+        rooms,
+    })
+}
+
 #[tauri::command]
 async fn allocate_exam(
     state: State<'_, AppState>,
+    token: String,
     subject_code: String,
     class_codes: Vec<String>,
     date: String,
     shift_code: String,
     room_number: String,
-) -> Result<AllocateExamResponse, String> {
+) -> AppResult<AllocateExamResponse> {
+    authorize(&state, &token, "allocate_exam")?;
+
     println!("Received data:");
     println!("Subject Code: {}", subject_code);
     println!("Class Codes: {:?}", class_codes);
@@ -325,242 +697,129 @@ async fn allocate_exam(
     println!("Shift Code: {}", shift_code);
     println!("Room Number: {}", room_number);
 
-    let mut conn = state.mysql_pool.get_conn().map_err(|e| format!("Failed to get connection: {}", e))?;
-    
-    let mut transaction = conn.start_transaction(TxOpts::default()).map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let request = ExamRequest {
+        subject_code: &subject_code,
+        class_codes: &class_codes,
+        date: &date,
+        shift_code: &shift_code,
+    };
 
-    // Check if there is already a transaction with the same shift_code and date
-    let existing_transactions: Vec<(String, String)> = transaction.exec(
-        "SELECT room_number, shift_code FROM transaction_header WHERE date = ? AND shift_code = ?",
-        (date.clone(), shift_code.clone()),
-    ).map_err(|e| format!("Failed to query existing transactions: {}", e))?;
+    book_exam(&state, &request, &[room_number])
+}
 
-    if !existing_transactions.is_empty() {
-        return Err(format!("A transaction with shift code {} already exists for the date {}", shift_code, date));
-    }
+#[tauri::command]
+async fn allocate_exam_split(
+    state: State<'_, AppState>,
+    token: String,
+    subject_code: String,
+    class_codes: Vec<String>,
+    date: String,
+    shift_code: String,
+    room_numbers: Vec<String>,
+) -> AppResult<AllocateExamResponse> {
+    authorize(&state, &token, "allocate_exam_split")?;
 
-    // Generate a transaction code: "TH" followed by 3 random digits
-    let mut rng = rand::thread_rng();
-    let transaction_code: String = format!("TH{:03}", rng.gen_range(0..1000));
+    println!("Received split allocation:");
+    println!("Subject Code: {}", subject_code);
+    println!("Class Codes: {:?}", class_codes);
+    println!("Date: {}", date);
+    println!("Shift Code: {}", shift_code);
+    println!("Room Numbers: {:?}", room_numbers);
 
-    println!("Generated Transaction Code: {}", transaction_code);
+    let request = ExamRequest {
+        subject_code: &subject_code,
+        class_codes: &class_codes,
+        date: &date,
+        shift_code: &shift_code,
+    };
 
-    // Insert into transaction header
-    let query = "INSERT INTO transaction_header (transaction_code, subject_code, shift_code, date, room_number) VALUES (?, ?, ?, ?, ?)";
-    transaction.exec_drop(query, (transaction_code.clone(), subject_code.clone(), shift_code.clone(), date.clone(), room_number.clone())).map_err(|e| format!("Failed to insert into transaction_header: {}", e))?;
-    
-    transaction.commit().map_err(|e| format!("Failed to commit transaction: {}", e))?;
-    
-    println!("Transaction committed successfully.");
-    
-    Ok(AllocateExamResponse {
-        transaction_code,
-        message: "Exam allocated successfully".to_string(),
-    })
+    book_exam(&state, &request, &room_numbers)
 }
 
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-struct EnrollmentsBySubject {
+struct ExamParticipant {
+    transaction_code: String,
+    room_number: String,
     class_code: String,
     nim: String,
-    subject_code: String,
+    name: String,
 }
 
+const EXAM_PARTICIPANTS_QUERY: &str = r"SELECT td.transaction_code, th.room_number, td.class_code, td.nim, u.name
+    FROM transaction_detail td
+    JOIN transaction_header th ON th.transaction_code = td.transaction_code
+    JOIN users u ON u.nim = td.nim";
+
 #[tauri::command]
-async fn get_enrollments_by_subject_code(state: State<'_, AppState>, subject_code: String) -> Result<Vec<EnrollmentsBySubject>, String> {
-    let mut conn = state.mysql_pool.get_conn().map_err(|e| format!("Failed to get connection: {}", e))?;
+async fn get_transaction_participants(state: State<'_, AppState>, token: String, transaction_code: String) -> AppResult<Vec<ExamParticipant>> {
+    authorize(&state, &token, "get_transaction_participants")?;
 
-    let query = format!("SELECT class_code, nim, subject_code FROM enrollments WHERE subject_code = '{}'", subject_code);
-    println!("SQL Query: {}", query); // Add debug print for SQL query
-    
-    let enrollments = conn.query_map(
-        query,
-        |(class_code, nim, subject_code)| EnrollmentsBySubject { class_code, nim, subject_code }
-    ).map_err(|e| format!("Failed to query enrollments: {}", e))?;
+    let mut conn = state.conn()?;
 
-    println!("Enrollments by Subject Code: {:?}", enrollments); // Add debug print for fetched enrollments
+    let participants = conn.exec_map(
+        format!("{} WHERE td.transaction_code = ? ORDER BY td.class_code, td.nim", EXAM_PARTICIPANTS_QUERY),
+        (transaction_code,),
+        |(transaction_code, room_number, class_code, nim, name)| ExamParticipant { transaction_code, room_number, class_code, nim, name },
+    ).map_err(|e| AppError::database("Failed to query participants", e))?;
 
-    Ok(enrollments)
+    Ok(participants)
 }
 
-fn create_users_table_if_not_exists(conn: &mut PooledConn) -> Result<(), mysql::Error> {
-    conn.exec_drop(
-        r"CREATE TABLE IF NOT EXISTS users (
-            bn_number VARCHAR(255) NOT NULL,
-            nim VARCHAR(255) PRIMARY KEY,
-            name VARCHAR(255) NOT NULL,
-            major VARCHAR(255),
-            role VARCHAR(255),
-            initial VARCHAR(255),
-            password VARCHAR(255)
-        )", 
-        ()
-    )
-}
-
-fn create_rooms_table_if_not_exists(conn: &mut PooledConn) -> Result<(), mysql::Error> {
-    conn.exec_drop(
-        r"CREATE TABLE IF NOT EXISTS rooms (
-            room_number VARCHAR(255) PRIMARY KEY,
-            room_capacity INT NOT NULL,
-            campus VARCHAR(255) NOT NULL
-        )", 
-        ()
-    )
-}
-
-fn create_shifts_table_if_not_exists(conn: &mut PooledConn) -> Result<(), mysql::Error> {
-    conn.exec_drop(
-        r"CREATE TABLE IF NOT EXISTS shifts (
-            shift_code VARCHAR(255) PRIMARY KEY,
-            start_time TIME NOT NULL,
-            end_time TIME NOT NULL
-        )", 
-        ()
-    )
-}
-
-fn create_subjects_table_if_not_exists(conn: &mut PooledConn) -> Result<(), mysql::Error> {
-    conn.exec_drop(
-        r"CREATE TABLE IF NOT EXISTS subjects (
-            subject_code VARCHAR(255) PRIMARY KEY,
-            subject_name VARCHAR(255) NOT NULL
-        )", 
-        ()
-    )
-}
-
-fn create_transaction_header_table_if_not_exists(conn: &mut PooledConn) -> Result<(), mysql::Error> {
-    conn.exec_drop(
-        r"CREATE TABLE IF NOT EXISTS transaction_header (
-            transaction_code VARCHAR(255) PRIMARY KEY,
-            subject_code VARCHAR(255) NOT NULL,
-            shift_code VARCHAR(255) NOT NULL,
-            room_number VARCHAR(255) NOT NULL,
-            date DATE NOT NULL,
-            proctor VARCHAR(255),
-            FOREIGN KEY (subject_code) REFERENCES subjects(subject_code),
-            FOREIGN KEY (shift_code) REFERENCES shifts(shift_code),
-            FOREIGN KEY (room_number) REFERENCES rooms(room_number)
-        )", 
-        ()
-    )
-}
-
-fn create_enrollment_table_if_not_exists(conn: &mut PooledConn) -> Result<(), mysql::Error> {
-    conn.exec_drop(
-        r"CREATE TABLE IF NOT EXISTS enrollments (
-            subject_code VARCHAR(255) NOT NULL,
-            nim VARCHAR(255) NOT NULL,
-            class_code VARCHAR(255) PRIMARY KEY,
-            FOREIGN KEY (subject_code) REFERENCES subjects(subject_code),
-            FOREIGN KEY (nim) REFERENCES users(nim)
-        )",
-        ()
-    )
-}
-
-async fn insert_users(conn: &mut PooledConn) -> Result<(), ()> {
-    let users = get_all_users().await.map_err(|_| ())?;
-
-    for user in users {
-        conn.exec_drop(
-            r"INSERT INTO users (bn_number, name, major, initial, nim, role) 
-            VALUES (:bn_number, :name, :major, :initial, :nim, :role)
-            ON DUPLICATE KEY UPDATE 
-                bn_number = VALUES(bn_number)", 
-            params!{
-                "bn_number" => user.bn_number.into_inner(),
-                "initial" => user.initial.unwrap_or_default(),
-                "major" => user.major,
-                "name" => user.name,
-                "nim" => user.nim,
-                "role" => user.role,
-            },
-        ).expect("Failed to insert")
-    }    
+#[tauri::command]
+async fn get_exam_participants(state: State<'_, AppState>, token: String, exam_code: String) -> AppResult<Vec<ExamParticipant>> {
+    authorize(&state, &token, "get_exam_participants")?;
 
-    Ok(())
-}
+    let mut conn = state.conn()?;
 
-#[tauri::command]
-async fn insert_rooms(conn: &mut PooledConn) -> Result<(), ()> {
-    let rooms = get_all_room().await?;
+    let participants = conn.exec_map(
+        format!("{} WHERE th.exam_code = ? ORDER BY th.room_number, td.class_code, td.nim", EXAM_PARTICIPANTS_QUERY),
+        (exam_code,),
+        |(transaction_code, room_number, class_code, nim, name)| ExamParticipant { transaction_code, room_number, class_code, nim, name },
+    ).map_err(|e| AppError::database("Failed to query participants", e))?;
 
-    for room in rooms {
-        conn.exec_drop(
-            r"INSERT INTO rooms (room_number, room_capacity, campus) 
-            VALUES (:room_number, :room_capacity, :campus)
-            ON DUPLICATE KEY UPDATE 
-                room_number = VALUES(room_number)",
-            params!{
-                "room_number" => room.room_number,
-                "room_capacity" => room.room_capacity,
-                "campus" => room.campus
-            },
-        ).map_err(|err| {
-            println!("Failed to insert room: {:?}", err);
-            ()
-        })?;
-    }    
+    Ok(participants)
+}
 
-    Ok(())
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct EnrollmentsBySubject {
+    class_code: String,
+    nim: String,
+    subject_code: String,
 }
 
 #[tauri::command]
-async fn insert_shifts(conn: &mut PooledConn) -> Result<(), ()> {
-    let shift = vec![
-        ("1", "07:00:00", "09:00:00"),
-        ("2", "09:00:00", "11:00:00"),
-        ("3", "11:00:00", "13:00:00"),
-        ("4", "13:00:00", "15:00:00"),
-        ("5", "15:00:00", "17:00:00"),
-        ("6", "17:00:00", "19:00:00"),
-        ("7", "19:00:00", "21:00:00"),
-    ];
+async fn get_enrollments_by_subject_code(state: State<'_, AppState>, token: String, subject_code: String) -> AppResult<Vec<EnrollmentsBySubject>> {
+    authenticate(&state, &token)?;
 
-    for (shift_code, start_time, end_time) in shift {
-        conn.exec_drop(
-            r"INSERT INTO shifts (shift_code, start_time, end_time) 
-            VALUES (:shift_code, :start_time, :end_time) ON DUPLICATE KEY UPDATE 
-            shift_code = VALUES(shift_code)",
-            params!{
-                "shift_code" => shift_code,
-                "start_time" => start_time,
-                "end_time" => end_time,
-            },
-        ).expect("Failed to insert shift schedule");
-    }
+    let mut conn = state.conn()?;
 
-    Ok(())
+    let query = "SELECT class_code, nim, subject_code FROM enrollments WHERE subject_code = ?";
+    println!("SQL Query: {}", query); // Add debug print for SQL query
+    
+    let enrollments = conn.exec_map(
+        query,
+        (subject_code,),
+        |(class_code, nim, subject_code)| EnrollmentsBySubject { class_code, nim, subject_code }
+    ).map_err(|e| AppError::database("Failed to query enrollments", e))?;
+
+    println!("Enrollments by Subject Code: {:?}", enrollments); // Add debug print for fetched enrollments
+
+    Ok(enrollments)
 }
 
 #[tauri::command]
-async fn insert_subjects(conn: &mut PooledConn) -> Result<(), ()> {
-    let subjects = get_all_subject().await?;
+async fn get_schema_version(state: State<'_, AppState>, token: String) -> AppResult<migrations::SchemaVersion> {
+    authenticate(&state, &token)?;
 
-    for subject in subjects {
-        conn.exec_drop(
-            r"INSERT INTO subjects (subject_code, subject_name) 
-            VALUES (:subject_code, :subject_name)
-            ON DUPLICATE KEY UPDATE 
-            subject_code = VALUES(subject_code)",
-            params!{
-                "subject_code" => subject.subject_code,
-                "subject_name" => subject.subject_name
-            },
-        ).map_err(|err| {
-            println!("Failed to insert room: {:?}", err);
-            ()
-        })?;
-    }    
+    let mut conn = state.conn()?;
 
-    Ok(())
+    migrations::schema_version(&mut conn).map_err(|e| AppError::database("Failed to read schema version", e))
 }
 
-#[tauri::command]
-async fn insert_transaction_header(conn: &mut PooledConn) -> Result<(), ()> {
+/// Demo exams for development, added by `sr-exam --seed-demo-data`. They
+/// reference rooms and subjects, so run it after the first sync.
+fn seed_demo_transactions(conn: &mut PooledConn) -> AppResult<()> {
     let transaction_headers = vec![
         ("TH001", "ACCT6300003", "1", "601", "2024-06-01"),
         ("TH002", "ACCT6300003", "2", "602", "2024-06-01"),
@@ -589,40 +848,59 @@ async fn insert_transaction_header(conn: &mut PooledConn) -> Result<(), ()> {
                 "room_number" => room_number,
                 "date" => date,
             },
-        ).expect("Failed to insert transaction header");
+        ).map_err(|e| AppError::database("Failed to insert transaction header", e))?;
     }
 
-    Ok(())  
+    Ok(())
 }
 
-#[tauri::command]
-async fn insert_enrollment(conn: &mut PooledConn) -> Result<(), ()> {
-    let enrollments: Vec<Enrollment> = get_all_enrollment().await?;
 
-    for enrollment in enrollments {
-        conn.exec_drop(
-            r"INSERT INTO enrollments (subject_code, nim, class_code) 
-            VALUES (:subject_code, :nim, :class_code)
-            ON DUPLICATE KEY UPDATE 
-                class_code = VALUES(class_code)",
-            params!{
-                "subject_code" => enrollment.subject_code,
-                "nim" => enrollment.nim,
-                "class_code" => enrollment.class_code
-            },
-        ).map_err(|err| {
-            println!("Failed to insert room: {:?}", err);
-            ()
-        })?;
-    }    
+#[derive(Clone, Debug, Serialize)]
+struct LoginResponse {
+    /// Passed back by the window to every command that needs the caller.
+    /// `None` while a two-factor code is still needed.
+    token: Option<String>,
+    login_type: String,
+    must_change_password: bool,
+    must_enroll_two_factor: bool,
+    /// Set when the password was right and `verify_two_factor` must finish the login.
+    two_factor_challenge: Option<String>,
+}
 
-    Ok(())
+impl LoginResponse {
+    fn for_session(token: String, user: &CurrentUser) -> Self {
+        Self {
+            token: Some(token),
+            login_type: user.login_type.clone(),
+            must_change_password: user.must_change_password,
+            must_enroll_two_factor: user.must_enroll_two_factor,
+            two_factor_challenge: None,
+        }
+    }
 }
 
+fn start_session(state: &AppState, remember: &RememberMe, user: CurrentUser, remember_me: bool) -> AppResult<LoginResponse> {
+    let token = state.sessions.create(user.clone())?;
+    if remember_me {
+        // Logging in still works when the session cannot be remembered
+        if let Err(e) = remember.save(&token, &user) {
+            println!("Failed to remember session: {}", e);
+        }
+    }
+    Ok(LoginResponse::for_session(token, &user))
+}
 
 #[tauri::command]
-fn login(username: String, password: String, state: State<'_, AppState>) -> Result<Option<String>, String> {
-    let mut conn: PooledConn = state.mysql_pool.get_conn().map_err(|e| format!("Failed to get connection: {}", e))?;
+fn login(
+    username: String,
+    password: String,
+    remember_me: Option<bool>,
+    state: State<'_, AppState>,
+    remember: State<'_, RememberMe>,
+) -> AppResult<LoginResponse> {
+    let mut conn: PooledConn = state.conn()?;
+
+    lockout::check(&mut conn, &state.login, &username, &state.machine)?;
 
     // Fetch the bn_number, name, major, initial, nim, role, and password
     let result: Option<(String, String, String, Option<String>, String, String, Option<String>)> = conn.exec_first(
@@ -630,192 +908,761 @@ fn login(username: String, password: String, state: State<'_, AppState>) -> Resu
         params! {
             "username" => username.clone(),
         }
-    ).map_err(|e| format!("Failed to execute query: {}", e))?;
-
-    if let Some((bn_number, name, major, initial, nim, role, stored_password)) = result {
-        // Determine the login type
-        let user_type = if username.chars().all(char::is_numeric) {
-            "nim"
-        } else {
-            "initial"
-        };
+    ).map_err(|e| AppError::database("Failed to execute query", e))?;
 
-        // Check if the username and password match the stored credentials
-        let is_password_correct = match stored_password {
-            Some(stored_hash) => match verify(&password, &stored_hash) {
-                Ok(is_verified) => is_verified,
-                Err(e) => {
-                    println!("Failed to verify password: {}", e);
-                    return Err(format!("Failed to verify password: {}", e));
-                }
-            },
-            None => match user_type {
-                "nim" => password == nim,
-                "initial" => match &initial {
-                    Some(init) => password == *init,
-                    None => false,
-                },
-                _ => false,
+    let invalid_credentials = "Invalid username or password";
+
+    // Unknown usernames count as failures too, so guessing them is throttled the same way
+    let Some((bn_number, name, major, initial, nim, role, stored_password)) = result else {
+        return Err(lockout::record_failure(&mut conn, &state.login, &username, &state.machine, invalid_credentials)?);
+    };
+
+    // Determine the login type
+    let user_type = if username.chars().all(char::is_numeric) {
+        "nim"
+    } else {
+        "initial"
+    };
+
+    // No stored hash means the NIM or initial is still the password
+    let must_change_password = stored_password.is_none();
+
+    // Check if the username and password match the stored credentials
+    let is_password_correct = match &stored_password {
+        Some(stored_hash) => hashing::verify(&password, stored_hash)?,
+        None => match user_type {
+            "nim" => password == nim,
+            "initial" => match &initial {
+                Some(init) => password == *init,
+                None => false,
             },
-        };
+            _ => false,
+        },
+    };
 
-        if is_password_correct {
-            let user = CurrentUser { bn_number, nim, name, major, role, initial, login_type: user_type.to_string() };
-            *state.user.lock().map_err(|e| format!("Failed to lock mutex: {}", e))? = Some(user);
-            Ok(Some(user_type.to_string()))
-        } else {
-            Ok(None)
+    if !is_password_correct {
+        println!("Failed login for {} from {}", username, state.machine);
+        return Err(lockout::record_failure(&mut conn, &state.login, &username, &state.machine, invalid_credentials)?);
+    }
+
+    // Only checked once the password is right, so guessing cannot reveal suspended accounts
+    suspension::check(&mut conn, &bn_number)?;
+
+    // Suspended accounts are left alone; their hash is upgraded once they are reinstated
+    if let Some(stored_hash) = &stored_password {
+        hashing::upgrade(&mut conn, &state.password_policy, &bn_number, stored_hash, &password);
+    }
+
+    if must_change_password {
+        println!("{} logged in with a default password and must change it", bn_number);
+    }
+
+    let two_factor_enabled = two_factor::is_enabled(&mut conn, &bn_number)?;
+    let must_enroll_two_factor = !two_factor_enabled && two_factor::required_for(&mut conn, &role)?;
+
+    let user = CurrentUser {
+        bn_number,
+        nim,
+        name,
+        major,
+        role,
+        initial,
+        login_type: user_type.to_string(),
+        must_change_password,
+        must_enroll_two_factor,
+        impersonated_by: None,
+    };
+
+    if two_factor_enabled {
+        let challenge = state.pending_logins.create(&username, user.clone(), remember_me.unwrap_or(false))?;
+        return Ok(LoginResponse {
+            token: None,
+            login_type: user.login_type,
+            must_change_password,
+            must_enroll_two_factor,
+            two_factor_challenge: Some(challenge),
+        });
+    }
+
+    lockout::record(&mut conn, &username, &state.machine, true)?;
+    start_session(&state, &remember, user, remember_me.unwrap_or(false))
+}
+
+/// Second login step for users with two-factor authentication: `code` is the
+/// current authenticator code or one of their recovery codes.
+#[tauri::command]
+fn verify_two_factor(
+    challenge: String,
+    code: String,
+    state: State<'_, AppState>,
+    remember: State<'_, RememberMe>,
+) -> AppResult<LoginResponse> {
+    let pending = state.pending_logins.get(&challenge)?;
+    let mut conn = state.conn()?;
+
+    lockout::check(&mut conn, &state.login, &pending.username, &state.machine)?;
+
+    if !two_factor::verify_code(&mut conn, &pending.user.bn_number, &code)? {
+        println!("Failed two-factor code for {} from {}", pending.username, state.machine);
+        let error = lockout::record_failure(&mut conn, &state.login, &pending.username, &state.machine, "Invalid two-factor code")?;
+        if error.code == ErrorCode::AccountLocked {
+            state.pending_logins.remove(&challenge)?;
         }
-    } else {
-        Ok(None)
+        return Err(error);
     }
+
+    state.pending_logins.remove(&challenge)?;
+    lockout::record(&mut conn, &pending.username, &state.machine, true)?;
+    start_session(&state, &remember, pending.user, pending.remember_me)
 }
 
 #[tauri::command]
-fn change_password(current_password: String, new_password: String, state: State<'_, AppState>) -> Result<String, String> {
-    let user_option = state.user.lock().unwrap();
-    if let Some(user) = &*user_option {
-        let mut conn: PooledConn = match state.mysql_pool.get_conn() {
-            Ok(conn) => conn,
-            Err(e) => {
-                println!("Failed to get connection: {}", e);
-                return Err(format!("Failed to get connection: {}", e));
-            }
-        };
+fn begin_two_factor_enrollment(state: State<'_, AppState>, token: String) -> AppResult<two_factor::Enrollment> {
+    let user = authenticate(&state, &token)?;
+    let mut conn = state.conn()?;
+    two_factor::begin_enrollment(&mut conn, &user)
+}
 
-        // Fetch the password field, nim, and initial from the database
-        let result: Option<(Option<String>, String, Option<String>)> = match conn.exec_first(
-            "SELECT password, nim, initial FROM users WHERE bn_number = :bn_number",
-            params! {
-                "bn_number" => user.bn_number.clone(),
-            }
-        ) {
-            Ok(result) => result,
-            Err(e) => {
-                println!("Failed to execute query: {}", e);
-                return Err(format!("Failed to execute query: {}", e));
-            }
-        };
+/// Enables two-factor with the first code from the authenticator and returns
+/// the recovery codes.
+#[tauri::command]
+fn confirm_two_factor_enrollment(state: State<'_, AppState>, token: String, code: String) -> AppResult<Vec<String>> {
+    let user = authenticate(&state, &token)?;
+    let mut conn = state.conn()?;
 
-        match result {
-            Some((stored_password_hash, nim, initial)) => {
-                // Check if the password is correct based on the login type or the stored hash
-                let is_password_correct = match stored_password_hash {
-                    Some(stored_hash) => match verify(&current_password, &stored_hash) {
-                        Ok(is_verified) => is_verified,
-                        Err(e) => {
-                            println!("Failed to verify password: {}", e);
-                            return Err(format!("Failed to verify password: {}", e));
-                        }
-                    },
-                    None => match user.login_type.as_str() {
-                        "nim" => current_password == nim,
-                        "initial" => initial.as_deref() == Some(&current_password),
-                        _ => false,
-                    },
-                };
-
-                if is_password_correct {
-                    match hash(&new_password, DEFAULT_COST) {
-                        Ok(new_password_hash) => {
-                            if let Err(e) = conn.exec_drop(
-                                "UPDATE users SET password = :password WHERE bn_number = :bn_number",
-                                params! {
-                                    "password" => new_password_hash,
-                                    "bn_number" => user.bn_number.clone(),
-                                }
-                            ) {
-                                println!("Failed to execute update: {}", e);
-                                return Err(format!("Failed to execute update: {}", e));
-                            }
-                            println!("Password changed successfully for user: {}", user.bn_number);
-                            Ok("Success".into())
-                        },
-                        Err(e) => {
-                            println!("Failed to hash new password: {}", e);
-                            Err(format!("Failed to hash new password: {}", e))
-                        }
-                    }
-                } else {
-                    println!("Current password is incorrect for user: {}", user.bn_number);
-                    Ok("Current password is incorrect".into())
-                }
-            },
-            None => {
-                println!("User not found: {}", user.bn_number);
-                Err("User not found".into())
-            }
+    let recovery_codes = two_factor::confirm_enrollment(&mut conn, &user.bn_number, &code)?;
+    state.sessions.update(&token, |current| current.must_enroll_two_factor = false)?;
+
+    println!("{} enabled two-factor authentication", user.bn_number);
+    Ok(recovery_codes)
+}
+
+#[tauri::command]
+fn disable_two_factor(state: State<'_, AppState>, token: String, code: String) -> AppResult<()> {
+    let user = authenticate(&state, &token)?;
+    let mut conn = state.conn()?;
+
+    if two_factor::required_for(&mut conn, &user.role)? {
+        return Err(AppError::new(
+            ErrorCode::Forbidden,
+            format!("Two-factor authentication is required for the {} role", user.role),
+        ));
+    }
+    if !two_factor::verify_code(&mut conn, &user.bn_number, &code)? {
+        return Err(AppError::new(ErrorCode::InvalidCredentials, "Invalid two-factor code"));
+    }
+
+    two_factor::disable(&mut conn, &user.bn_number)?;
+    println!("{} disabled two-factor authentication", user.bn_number);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_two_factor_roles(state: State<'_, AppState>, token: String) -> AppResult<Vec<String>> {
+    authorize(&state, &token, "get_two_factor_roles")?;
+    let mut conn = state.conn()?;
+    two_factor::required_roles(&mut conn)
+}
+
+/// Sets the roles whose users must enroll in two-factor before doing anything else.
+#[tauri::command]
+fn set_two_factor_roles(state: State<'_, AppState>, token: String, roles: Vec<String>) -> AppResult<()> {
+    let admin = authorize(&state, &token, "set_two_factor_roles")?;
+    let mut conn = state.conn()?;
+    two_factor::set_required_roles(&mut conn, &roles)?;
+
+    println!("{} required two-factor for roles {:?}", admin.bn_number, roles);
+    Ok(())
+}
+
+// bn_number, nim, name, major, role, initial and whether the password is unset
+type UserRow = (String, String, String, Option<String>, String, Option<String>, bool);
+
+/// The user as currently stored, or `None` when they no longer exist.
+fn load_user(conn: &mut PooledConn, bn_number: &str) -> AppResult<Option<CurrentUser>> {
+    let row: Option<UserRow> = conn
+        .exec_first(
+            "SELECT bn_number, nim, name, major, role, initial, password IS NULL FROM users WHERE bn_number = ?",
+            (bn_number,),
+        )
+        .map_err(|e| AppError::database("Failed to execute query", e))?;
+    let Some((bn_number, nim, name, major, role, initial, must_change_password)) = row else {
+        return Ok(None);
+    };
+
+    let must_enroll_two_factor = !two_factor::is_enabled(conn, &bn_number)? && two_factor::required_for(conn, &role)?;
+    // insert_users stores a missing initial as an empty string
+    let initial = initial.filter(|initial| !initial.is_empty());
+    Ok(Some(CurrentUser {
+        login_type: if initial.is_some() { "initial" } else { "nim" }.to_string(),
+        bn_number,
+        nim,
+        name,
+        major: major.unwrap_or_default(),
+        role,
+        initial,
+        must_change_password,
+        must_enroll_two_factor,
+        impersonated_by: None,
+    }))
+}
+
+/// Logs the window in with the "remember me" session of a previous run, if any.
+/// Only the token comes from the file; the user is reloaded so a changed role
+/// or a deleted account takes effect.
+#[tauri::command]
+fn restore_session(state: State<'_, AppState>, remember: State<'_, RememberMe>) -> AppResult<Option<LoginResponse>> {
+    let Some((token, remembered)) = remember.load() else {
+        return Ok(None);
+    };
+
+    let mut conn = state.conn()?;
+    let Some(user) = load_user(&mut conn, &remembered.bn_number)? else {
+        println!("Forgetting remembered session of deleted user {}", remembered.bn_number);
+        remember.forget(Some(&token));
+        return Ok(None);
+    };
+
+    if let Err(e) = suspension::check(&mut conn, &user.bn_number) {
+        if e.code == ErrorCode::AccountSuspended {
+            remember.forget(Some(&token));
         }
-    } else {
-        println!("No user logged in");
-        Err("No user logged in".into())
+        return Err(e);
+    }
+
+    println!("Restoring remembered session of {}", user.bn_number);
+    let response = LoginResponse::for_session(token.clone(), &user);
+    state.sessions.restore(&token, user)?;
+    Ok(Some(response))
+}
+
+#[tauri::command]
+fn change_password(
+    token: String,
+    current_password: String,
+    new_password: String,
+    state: State<'_, AppState>,
+    remember: State<'_, RememberMe>,
+) -> AppResult<String> {
+    let user = authenticate(&state, &token)?;
+    let mut conn: PooledConn = state.conn()?;
+
+    // Fetch the password field, nim, and initial from the database
+    let result: Option<(Option<String>, String, Option<String>)> = conn.exec_first(
+        "SELECT password, nim, initial FROM users WHERE bn_number = :bn_number",
+        params! {
+            "bn_number" => user.bn_number.clone(),
+        }
+    ).map_err(|e| AppError::database("Failed to execute query", e))?;
+
+    let (stored_password_hash, nim, initial) =
+        result.ok_or_else(|| AppError::not_found(format!("User {} not found", user.bn_number)))?;
+
+    // Check if the password is correct based on the login type or the stored hash
+    let is_password_correct = match &stored_password_hash {
+        Some(stored_hash) => hashing::verify(&current_password, stored_hash)?,
+        None => match user.login_type.as_str() {
+            "nim" => current_password == nim,
+            "initial" => initial.as_deref() == Some(&current_password),
+            _ => false,
+        },
+    };
+
+    if !is_password_correct {
+        println!("Current password is incorrect for user: {}", user.bn_number);
+        return Err(AppError::new(ErrorCode::InvalidCredentials, "Current password is incorrect"));
     }
+
+    let personal = password_policy::PersonalInfo { nim: &nim, initial: initial.as_deref(), name: &user.name };
+    password_policy::check(
+        &mut conn,
+        &state.password_policy,
+        &user.bn_number,
+        stored_password_hash.as_deref(),
+        &new_password,
+        &personal,
+    )?;
+
+    let new_password_hash = hashing::hash(&state.password_policy, &new_password)?;
+
+    let mut transaction = conn.start_transaction(TxOpts::default()).map_err(|e| AppError::database("Failed to start transaction", e))?;
+    transaction.exec_drop(
+        "UPDATE users SET password = :password WHERE bn_number = :bn_number",
+        params! {
+            "password" => &new_password_hash,
+            "bn_number" => user.bn_number.clone(),
+        }
+    ).map_err(|e| AppError::database("Failed to execute update", e))?;
+    password_policy::remember(&mut transaction, &user.bn_number, &new_password_hash)?;
+    transaction.commit().map_err(|e| AppError::database("Failed to commit transaction", e))?;
+
+    state.sessions.update(&token, |current| current.must_change_password = false)?;
+    // A remembered login made with the old password must not outlive it
+    remember.forget(Some(&token));
+
+    println!("Password changed successfully for user: {}", user.bn_number);
+    Ok("Success".into())
 }
 
 #[tauri::command]
-async fn update_user_role(state: State<'_, AppState>, bn_number: String, new_role: String) -> Result<(), String> {
+async fn update_user_role(state: State<'_, AppState>, token: String, bn_number: String, new_role: String) -> AppResult<()> {
+    let admin = authorize(&state, &token, "update_user_role")?;
+
     // Log the incoming request
     println!("Received request to update role for bn_number: {}, new_role: {}", bn_number, new_role);
     
-    // Attempt to get a connection from the pool
-    let mut conn = match state.mysql_pool.get_conn() {
-        Ok(conn) => conn,
-        Err(e) => {
-            println!("Failed to get connection from pool: {}", e);
-            return Err(format!("Failed to get connection: {}", e));
-        }
-    };
+    let mut conn = state.conn()?;
+
+    let user: Option<(String, Option<String>)> = conn.exec_first(
+        "SELECT nim, role FROM users WHERE bn_number = ?",
+        (&bn_number,),
+    ).map_err(|e| AppError::database("Failed to query user", e))?;
+    let (nim, old_role) = user.ok_or_else(|| AppError::not_found(format!("User {} not found", bn_number)))?;
 
     // Attempt to execute the update query
-    if let Err(e) = conn.exec_drop(
+    let mut transaction = conn.start_transaction(TxOpts::default()).map_err(|e| AppError::database("Failed to start transaction", e))?;
+    transaction.exec_drop(
         "UPDATE users SET role = :role WHERE bn_number = :bn_number",
         params! {
             "role" => new_role,
             "bn_number" => bn_number,
         }
-    ) {
-        println!("Failed to execute update query: {}", e);
-        return Err(format!("Failed to execute update: {}", e));
+    ).map_err(|e| AppError::database("Failed to execute update", e))?;
+    // So the next sync does not silently undo the change
+    sync::record_local_edit(&mut transaction, "users", &[&nim], "role", old_role.as_deref(), &admin.bn_number)?;
+    transaction.commit().map_err(|e| AppError::database("Failed to commit transaction", e))?;
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_locked_accounts(state: State<'_, AppState>, token: String) -> AppResult<Vec<lockout::LockedAccount>> {
+    authorize(&state, &token, "get_locked_accounts")?;
+
+    let mut conn = state.conn()?;
+    lockout::locked_accounts(&mut conn, &state.login)
+}
+
+#[tauri::command]
+async fn clear_account_lockout(state: State<'_, AppState>, token: String, username: String) -> AppResult<()> {
+    let admin = authorize(&state, &token, "clear_account_lockout")?;
+
+    let mut conn = state.conn()?;
+    lockout::clear(&mut conn, &username)?;
+
+    println!("{} cleared the login lockout of {}", admin.bn_number, username);
+    Ok(())
+}
+
+/// Gives `bn_number` a one-time code to set a new password with
+/// `reset_password`, for users who forgot theirs. Also lifts any lockout so
+/// the code can be used straight away.
+#[tauri::command]
+async fn issue_password_reset(
+    state: State<'_, AppState>,
+    token: String,
+    bn_number: String,
+) -> AppResult<password_reset::PasswordReset> {
+    let admin = authorize(&state, &token, "issue_password_reset")?;
+    let mut conn = state.conn()?;
+
+    let user: Option<(String, Option<String>)> = conn
+        .exec_first("SELECT nim, initial FROM users WHERE bn_number = ?", (&bn_number,))
+        .map_err(|e| AppError::database("Failed to execute query", e))?;
+    let (nim, initial) = user.ok_or_else(|| AppError::not_found(format!("User {} not found", bn_number)))?;
+
+    for username in std::iter::once(nim).chain(initial) {
+        match lockout::clear(&mut conn, &username) {
+            Err(e) if e.code != ErrorCode::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+
+    let reset = password_reset::issue(&mut conn, &bn_number, &admin.bn_number, &state.machine, state.password_policy.reset_code_minutes)?;
+    println!("{} issued a password reset code for {}", admin.bn_number, bn_number);
+    Ok(reset)
+}
+
+// bn_number, nim, initial, name and password hash of the user resetting their password
+type ResetAccount = (String, String, Option<String>, String, Option<String>);
+
+/// Sets a new password with a reset code from an admin. Needs no session;
+/// wrong codes count as failed logins.
+#[tauri::command]
+fn reset_password(username: String, code: String, new_password: String, state: State<'_, AppState>) -> AppResult<()> {
+    let mut conn = state.conn()?;
+
+    lockout::check(&mut conn, &state.login, &username, &state.machine)?;
+
+    let invalid_code = "Invalid or expired reset code";
+    let user: Option<ResetAccount> = conn
+        .exec_first(
+            "SELECT bn_number, nim, initial, name, password FROM users WHERE nim = :username OR initial = :username",
+            params! { "username" => &username },
+        )
+        .map_err(|e| AppError::database("Failed to execute query", e))?;
+    let Some((bn_number, nim, initial, name, stored_password_hash)) = user else {
+        return Err(lockout::record_failure(&mut conn, &state.login, &username, &state.machine, invalid_code)?);
+    };
+    let Some(reset_id) = password_reset::find(&mut conn, &bn_number, &code)? else {
+        println!("Invalid password reset code for {} from {}", username, state.machine);
+        return Err(lockout::record_failure(&mut conn, &state.login, &username, &state.machine, invalid_code)?);
+    };
+
+    let personal = password_policy::PersonalInfo { nim: &nim, initial: initial.as_deref(), name: &name };
+    password_policy::check(
+        &mut conn,
+        &state.password_policy,
+        &bn_number,
+        stored_password_hash.as_deref(),
+        &new_password,
+        &personal,
+    )?;
+
+    let new_password_hash = hashing::hash(&state.password_policy, &new_password)?;
+
+    let mut transaction = conn.start_transaction(TxOpts::default()).map_err(|e| AppError::database("Failed to start transaction", e))?;
+    password_reset::consume(&mut transaction, reset_id)?;
+    transaction
+        .exec_drop("UPDATE users SET password = ? WHERE bn_number = ?", (&new_password_hash, &bn_number))
+        .map_err(|e| AppError::database("Failed to execute update", e))?;
+    password_policy::remember(&mut transaction, &bn_number, &new_password_hash)?;
+    audit::record(&mut transaction, &bn_number, &state.machine, "password_reset_used", Some(&bn_number), serde_json::json!({ "reset_id": reset_id }))?;
+    transaction.commit().map_err(|e| AppError::database("Failed to commit transaction", e))?;
+
+    lockout::record(&mut conn, &username, &state.machine, true)?;
+    println!("{} reset their password with a reset code", bn_number);
+    Ok(())
+}
+
+/// The newest audit entries, only those about `bn_number` if given.
+#[tauri::command]
+async fn get_audit_log(
+    state: State<'_, AppState>,
+    token: String,
+    bn_number: Option<String>,
+    limit: Option<u32>,
+) -> AppResult<Vec<audit::AuditEntry>> {
+    authorize(&state, &token, "get_audit_log")?;
+    let mut conn = state.conn()?;
+    audit::entries(&mut conn, bn_number.as_deref(), limit.unwrap_or(200).min(1000))
+}
+
+/// Suspends an account until `suspended_until` (seconds since the Unix
+/// epoch), or until it is reinstated when none is given.
+#[tauri::command]
+async fn suspend_account(
+    state: State<'_, AppState>,
+    token: String,
+    bn_number: String,
+    reason: String,
+    suspended_until: Option<u64>,
+) -> AppResult<()> {
+    let admin = authorize(&state, &token, "suspend_account")?;
+
+    if reason.trim().is_empty() {
+        return Err(AppError::validation("A reason is required to suspend an account"));
+    }
+    if bn_number == admin.bn_number {
+        return Err(AppError::validation("You cannot suspend your own account"));
+    }
+    if suspended_until.is_some_and(|until| until <= clock::now()) {
+        return Err(AppError::validation("The end of the suspension must be in the future"));
+    }
+
+    let mut conn = state.conn()?;
+    suspension::suspend(&mut conn, &bn_number, &admin.bn_number, &state.machine, reason.trim(), suspended_until)?;
+
+    println!("{} suspended {}: {}", admin.bn_number, bn_number, reason.trim());
+    Ok(())
+}
+
+#[tauri::command]
+async fn reinstate_account(state: State<'_, AppState>, token: String, bn_number: String, reason: String) -> AppResult<()> {
+    let admin = authorize(&state, &token, "reinstate_account")?;
+
+    if reason.trim().is_empty() {
+        return Err(AppError::validation("A reason is required to reinstate an account"));
     }
 
+    let mut conn = state.conn()?;
+    suspension::reinstate(&mut conn, &bn_number, &admin.bn_number, &state.machine, reason.trim())?;
+
+    println!("{} reinstated {}: {}", admin.bn_number, bn_number, reason.trim());
     Ok(())
 }
 
+#[tauri::command]
+async fn get_suspended_accounts(state: State<'_, AppState>, token: String) -> AppResult<Vec<suspension::SuspendedAccount>> {
+    authorize(&state, &token, "get_suspended_accounts")?;
+    let mut conn = state.conn()?;
+    suspension::suspended_accounts(&mut conn)
+}
+
+/// Lets an admin see the app exactly as `bn_number` does, without their
+/// password. The session runs as that user, read-only, until
+/// `stop_impersonation` or logout.
+#[tauri::command]
+async fn start_impersonation(state: State<'_, AppState>, token: String, bn_number: String) -> AppResult<CurrentUser> {
+    let admin = authorize(&state, &token, "start_impersonation")?;
+
+    if bn_number == admin.bn_number {
+        return Err(AppError::validation("You cannot view the app as yourself"));
+    }
+
+    let mut conn = state.conn()?;
+    let target = impersonation::load_target(&mut conn, &bn_number)?;
+    state.sessions.impersonate(&token, target)?;
+    // Never leave an impersonation running that the audit log does not show
+    if let Err(e) = audit::record(&mut conn, &admin.bn_number, &state.machine, "impersonation_started", Some(&bn_number), serde_json::json!({})) {
+        state.sessions.stop_impersonating(&token)?;
+        return Err(e);
+    }
+
+    println!("{} started viewing as {}", admin.bn_number, bn_number);
+    authenticate(&state, &token)
+}
+
+/// Ends `start_impersonation` and returns the admin's own user.
+#[tauri::command]
+async fn stop_impersonation(state: State<'_, AppState>, token: String) -> AppResult<CurrentUser> {
+    authenticate(&state, &token)?;
+    let (admin, target) = state.sessions.stop_impersonating(&token)?;
+
+    let mut conn = state.conn()?;
+    impersonation::record_stop(&mut conn, &state.machine, &admin, &target, "admin")?;
+
+    println!("{} stopped viewing as {}", admin.bn_number, target.bn_number);
+    Ok(admin)
+}
+
+/// The newest sync runs with what each changed.
+#[tauri::command]
+async fn get_sync_runs(state: State<'_, AppState>, token: String, limit: Option<u32>) -> AppResult<Vec<sync::SyncRun>> {
+    authorize(&state, &token, "get_sync_runs")?;
+    let mut conn = state.conn()?;
+    sync::runs(&mut conn, limit.unwrap_or(50).min(500))
+}
+
+// Public so the login screen can explain an outage; only callers with a
+// session see the details
+#[tauri::command]
+async fn get_system_health(state: State<'_, AppState>, token: Option<String>) -> AppResult<SystemHealth> {
+    let database = health::probe_database(&state.mysql_pool);
+    state.health.record(Dependency::Database, &database);
+
+    // The schema could not be migrated if MySQL was down at startup; catch up once it is back
+    if database.is_ok() && !state.health.is_up(Dependency::Schema) {
+        ensure_schema(&state.mysql_pool, &state.health);
+    }
+
+    let graphql = health::probe_graphql(&state.graphql).await;
+    state.health.record(Dependency::Graphql, &graphql);
+
+    let health = state.health.snapshot();
+    match token.map(|token| authenticate(&state, &token)) {
+        Some(Ok(_)) => Ok(health),
+        _ => Ok(health.without_details()),
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct SessionExpired {
+    token: String,
+}
+
+/// Closes idle sessions every `SESSION_SWEEP_INTERVAL` and tells the windows
+/// through `session://expired`, so the one holding the token returns to login.
+fn sweep_idle_sessions(app: tauri::AppHandle) {
+    loop {
+        std::thread::sleep(SESSION_SWEEP_INTERVAL);
+
+        let expired = match app.state::<AppState>().sessions.expire_idle() {
+            Ok(expired) => expired,
+            Err(e) => {
+                println!("Failed to expire idle sessions: {}", e);
+                continue;
+            }
+        };
+        for (token, session) in expired {
+            println!("Session of {} expired after being idle", session.user.bn_number);
+            end_impersonation(&app.state::<AppState>(), &session, "idle_timeout");
+            // An idle timeout also ends a remembered login
+            app.state::<RememberMe>().forget(Some(&token));
+            if let Err(e) = app.emit_all("session://expired", SessionExpired { token }) {
+                println!("Failed to emit session://expired: {}", e);
+            }
+        }
+    }
+}
+
+fn open_pool(database: &DatabaseConfig, health: &Health) -> Pool {
+    match Pool::new(database.opts()) {
+        Ok(pool) => {
+            health.record(Dependency::Database, &Ok(format!("Connected to {}", database.host)));
+            pool
+        }
+        Err(e) => {
+            health.record(Dependency::Database, &Err(format!("Failed to connect to MySQL at {}: {}", database.host, e)));
+            Pool::new(database.lazy_opts()).expect("Failed to create MySQL pool")
+        }
+    }
+}
+
+/// Payload of `sync://done`.
+#[derive(Clone, Serialize)]
+struct SyncDone {
+    report: Option<sync::SyncReport>,
+    error: Option<AppError>,
+}
+
+/// Runs one sync unless one is already running, telling every window how it
+/// goes through `sync://progress` and `sync://done`.
+async fn run_sync(app: &tauri::AppHandle, triggered_by: &str) -> AppResult<sync::SyncReport> {
+    let state = app.state::<AppState>();
+    let _running = state
+        .sync_lock
+        .try_start()
+        .ok_or_else(|| AppError::new(ErrorCode::SyncInProgress, "A sync is already running"))?;
+
+    // The schema could not be migrated if MySQL was down at startup; catch up once it is back
+    if !state.health.is_up(Dependency::Schema) {
+        ensure_schema(&state.mysql_pool, &state.health);
+    }
+    let result = if state.health.is_up(Dependency::Schema) {
+        let progress = |progress: sync::Progress| {
+            if let Err(e) = app.emit_all("sync://progress", progress) {
+                println!("Failed to emit sync://progress: {}", e);
+            }
+        };
+        sync::run(&state.mysql_pool, &state.graphql, &state.sync, triggered_by, &progress).await
+    } else {
+        Err(AppError::new(ErrorCode::DatabaseUnavailable, "Cannot sync before the database schema is ready"))
+    };
+
+    if result.is_ok() {
+        state.health.record(Dependency::Graphql, &Ok(format!("{} reachable", state.graphql.endpoint)));
+    }
+    state.health.record(Dependency::Sync, &result.as_ref().map(sync::SyncReport::summary).map_err(|e| e.message.clone()));
+
+    let done = match &result {
+        Ok(report) => SyncDone { report: Some(report.clone()), error: None },
+        Err(e) => SyncDone { report: None, error: Some(e.clone()) },
+    };
+    if let Err(e) = app.emit_all("sync://done", done) {
+        println!("Failed to emit sync://done: {}", e);
+    }
+    result
+}
+
+/// Syncs at startup and then every `interval`, or only once without one.
+fn sync_periodically(app: tauri::AppHandle, interval: Option<Duration>) {
+    let mut triggered_by = "startup";
+    loop {
+        if let Err(e) = task::block_on(run_sync(&app, triggered_by)) {
+            println!("Background sync failed: {}", e);
+        }
+        let Some(interval) = interval else {
+            return;
+        };
+        std::thread::sleep(interval);
+        triggered_by = "schedule";
+    }
+}
+
+/// Syncs now instead of waiting for the next background sync.
+#[tauri::command]
+async fn trigger_sync(app: tauri::AppHandle, state: State<'_, AppState>, token: String) -> AppResult<sync::SyncReport> {
+    let admin = authorize(&state, &token, "trigger_sync")?;
+    println!("{} triggered a sync", admin.bn_number);
+    run_sync(&app, &admin.bn_number).await
+}
+
+fn ensure_schema(pool: &Pool, health: &Health) {
+    let result = pool
+        .get_conn()
+        .map_err(|e| format!("Failed to get connection: {}", e))
+        .and_then(|mut conn| migrations::migrate(&mut conn))
+        .map(|version| format!("Schema at version {}", version));
+    health.record(Dependency::Schema, &result);
+}
+
 fn main() {
-    let mysql_config = MySQLConfig::new(
-        "root".to_string(),
-        "".to_string(),
-        "localhost".to_string(),
-        "sr-exam".to_string(),
-    );
+    let config = AppConfig::load().unwrap_or_else(|e| panic!("{}", e));
+    let permissions = Permissions::with_overrides(config.permissions.clone());
+    // Already checked by AppConfig::validate
+    let transaction_code_format = CodeFormat::parse(&config.exams.transaction_code_format).expect("Invalid transaction code format");
+
+    let health = Health::default();
+    let pool = open_pool(&config.database, &health);
+
+    // `sr-exam --rollback-schema <version>` reverts migrations and exits.
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(position) = args.iter().position(|arg| arg == "--rollback-schema") {
+        let target: u32 = args
+            .get(position + 1)
+            .and_then(|version| version.parse().ok())
+            .expect("--rollback-schema needs a target version");
+        let mut conn = pool.get_conn().expect("Failed to get MySQL connection");
+        let version = migrations::rollback(&mut conn, target).expect("Failed to roll back schema");
+        println!("Schema rolled back to version {}", version);
+        return;
+    }
 
-    let mysql_url = mysql_config.format_url();
-    let pool = Pool::new(&*mysql_url).expect("Failed to create MySQL pool");
-    {
+    // `sr-exam --seed-demo-data` adds the demo exams for development and exits.
+    if args.iter().any(|arg| arg == "--seed-demo-data") {
         let mut conn = pool.get_conn().expect("Failed to get MySQL connection");
-        create_users_table_if_not_exists(&mut conn).expect("Failed to create users table");
-        create_rooms_table_if_not_exists(&mut conn).expect("Failed to create rooms table");
-        create_shifts_table_if_not_exists(&mut conn).expect("Failed to create shift table");
-        create_subjects_table_if_not_exists(&mut conn).expect("Failed to create subject table");
-        create_enrollment_table_if_not_exists(&mut conn).expect("Failed to create enrollment table");
-        create_transaction_header_table_if_not_exists(&mut conn).expect("Failed to create transaction_header table");
-
-        task::block_on(async {
-            insert_users(&mut conn).await.expect("Failed to insert");
-            insert_rooms(&mut conn).await.expect("Failed to insert");
-            insert_shifts(&mut conn).await.expect("Failed to insert");
-            insert_subjects(&mut conn).await.expect("Failed to insert");
-            insert_enrollment(&mut conn).await.expect("Failed to insert");
-            insert_transaction_header(&mut conn).await.expect("Failed to insert transaction headers");
-        });
+        migrations::migrate(&mut conn).expect("Failed to migrate schema");
+        seed_demo_transactions(&mut conn).expect("Failed to seed demo data");
+        println!("Seeded demo exams");
+        return;
     }
 
+    // Failures below are recorded in `health` instead of aborting, so the window
+    // still opens and serves whatever was synced last time.
+    if health.is_up(Dependency::Database) {
+        ensure_schema(&pool, &health);
+    }
+
+    let remember_me = config.session.remember_me();
+    let sync_interval = config.sync.interval();
+    let handler = tauri::generate_handler![login, restore_session, logout, change_password, get_current_user, get_all_users, get_all_subject, get_all_room, get_scheduled_rooms, get_all_shifts, get_all_enrollment, get_enrollments_by_subject_code, refresh_user_by_nim, refresh_user_by_initial, refresh_user_with_role, refresh_enrollment_by_nim, refresh_room_by_campus, refresh_subject_by_code, update_user_role, allocate_exam, allocate_exam_split, view_transaction, update_transaction_proctor, get_transaction_participants, get_exam_participants, get_schema_version, get_system_health, get_locked_accounts, clear_account_lockout, verify_two_factor, begin_two_factor_enrollment, confirm_two_factor_enrollment, disable_two_factor, get_two_factor_roles, set_two_factor_roles, issue_password_reset, reset_password, get_audit_log, suspend_account, reinstate_account, get_suspended_accounts, start_impersonation, stop_impersonation, get_sync_runs, trigger_sync];
+
     tauri::Builder::default()
         .manage(AppState {
-            user: Mutex::new(None),
+            sessions: SessionStore::new(config.session.idle_timeout()),
             mysql_pool: pool,
+            permissions,
+            transaction_code_format,
+            graphql: config.graphql,
+            health,
+            login: config.login,
+            machine: lockout::machine_name(),
+            password_policy: config.password,
+            pending_logins: two_factor::PendingLogins::default(),
+            sync: config.sync,
+            sync_lock: sync::SyncLock::default(),
+        })
+        .setup(move |app| {
+            app.manage(RememberMe::new(app.path_resolver().app_data_dir(), remember_me));
+
+            let handle = app.handle();
+            std::thread::spawn(move || sweep_idle_sessions(handle));
+            // In the background so the window opens without waiting for GraphQL
+            let handle = app.handle();
+            std::thread::spawn(move || sync_periodically(handle, sync_interval));
+            Ok(())
+        })
+        .invoke_handler(move |invoke| {
+            // Checked here rather than in each command so none of them can forget it
+            let window = invoke.message.window();
+            let token = invoke.message.payload().get("token").and_then(|token| token.as_str());
+            if let Err(e) = require_account_ready(&window.state::<AppState>(), invoke.message.command(), token) {
+                invoke.resolver.reject(e);
+                return;
+            }
+            handler(invoke)
         })
-        .invoke_handler(tauri::generate_handler![login, logout, change_password, get_current_user, get_all_users, get_all_subject, get_all_room, get_scheduled_rooms, get_all_shifts, get_all_enrollment, get_enrollments_by_subject_code, update_user_role, allocate_exam, view_transaction, update_transaction_proctor])
         .run(tauri::generate_context!())
         .expect("Error while running Tauri application");
-}
\ No newline at end of file
+}
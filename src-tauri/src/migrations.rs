@@ -0,0 +1,227 @@
+use mysql::prelude::*;
+use mysql::PooledConn;
+use serde::Serialize;
+
+/// Named lock that keeps two lab machines from migrating the shared database at once.
+const LOCK_NAME: &str = "sr_exam_schema_migrations";
+const LOCK_TIMEOUT_SECS: u32 = 60;
+
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+macro_rules! migration {
+    ($version:expr, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            up: include_str!(concat!("../migrations/", $name, ".up.sql")),
+            down: include_str!(concat!("../migrations/", $name, ".down.sql")),
+        }
+    };
+}
+
+/// Every schema change, oldest first. Versions must be strictly increasing and
+/// a released migration must never be edited; add a new one instead.
+pub const MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_initial_schema"),
+    migration!(2, "0002_enrollments_composite_key"),
+    migration!(3, "0003_transaction_room_slot_unique"),
+    migration!(4, "0004_transaction_exam_code"),
+    migration!(5, "0005_transaction_detail"),
+    migration!(6, "0006_transaction_sequences"),
+    migration!(7, "0007_login_attempts"),
+    migration!(8, "0008_password_history"),
+    migration!(9, "0009_two_factor"),
+    migration!(10, "0010_password_resets"),
+    migration!(11, "0011_account_suspension"),
+    migration!(12, "0012_sync_runs"),
+    migration!(13, "0013_local_edits"),
+    migration!(14, "0014_sync_run_trigger"),
+    migration!(15, "0015_seed_shifts"),
+];
+
+/// Checks run before migrations that cannot apply to some existing data:
+/// the version, what is in the way and a query returning one line per
+/// offending row. The migration is not attempted while any row is returned.
+const PRECHECKS: &[(u32, &str, &str)] = &[(
+    3,
+    "rooms booked more than once for the same date and shift; keep one exam per slot and move or delete the others",
+    r"SELECT CONCAT(date, ' shift ', shift_code, ' room ', room_number, ': ', GROUP_CONCAT(transaction_code ORDER BY transaction_code SEPARATOR ', '))
+    FROM transaction_header
+    GROUP BY date, shift_code, room_number
+    HAVING COUNT(*) > 1",
+)];
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AppliedMigration {
+    pub version: u32,
+    pub name: String,
+    pub applied_at: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SchemaVersion {
+    pub current: u32,
+    pub latest: u32,
+    pub applied: Vec<AppliedMigration>,
+}
+
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+fn ensure_version_table(conn: &mut PooledConn) -> Result<(), mysql::Error> {
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS schema_version (
+            version INT UNSIGNED PRIMARY KEY,
+            name VARCHAR(255) NOT NULL,
+            applied_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )",
+    )
+}
+
+pub fn current_version(conn: &mut PooledConn) -> Result<u32, mysql::Error> {
+    ensure_version_table(conn)?;
+    let version: Option<Option<u32>> = conn.query_first("SELECT MAX(version) FROM schema_version")?;
+    Ok(version.flatten().unwrap_or(0))
+}
+
+pub fn schema_version(conn: &mut PooledConn) -> Result<SchemaVersion, mysql::Error> {
+    let current = current_version(conn)?;
+    let applied = conn.query_map(
+        "SELECT version, name, CAST(applied_at AS CHAR) FROM schema_version ORDER BY version",
+        |(version, name, applied_at)| AppliedMigration { version, name, applied_at },
+    )?;
+
+    Ok(SchemaVersion {
+        current,
+        latest: latest_version(),
+        applied,
+    })
+}
+
+/// Splits a migration script into single statements. Scripts must not contain
+/// semicolons inside string literals.
+fn statements(script: &str) -> impl Iterator<Item = String> + '_ {
+    script.split(';').filter_map(|statement| {
+        let sql: Vec<&str> = statement
+            .lines()
+            .filter(|line| !line.trim_start().starts_with("--"))
+            .collect();
+        let sql = sql.join("\n");
+        let sql = sql.trim();
+        if sql.is_empty() {
+            None
+        } else {
+            Some(sql.to_string())
+        }
+    })
+}
+
+fn run_script(conn: &mut PooledConn, script: &str) -> Result<(), mysql::Error> {
+    for statement in statements(script) {
+        conn.query_drop(statement)?;
+    }
+    Ok(())
+}
+
+// Describes the rows that would make `migration` fail, if any
+fn precheck(conn: &mut PooledConn, migration: &Migration) -> Result<Option<String>, String> {
+    let Some((_, problem, query)) = PRECHECKS.iter().find(|(version, _, _)| *version == migration.version) else {
+        return Ok(None);
+    };
+
+    let rows: Vec<String> = conn
+        .query(*query)
+        .map_err(|e| format!("Failed to check data before {}: {}", migration.name, e))?;
+    if rows.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "Migration {} cannot be applied: {} ({})",
+        migration.name,
+        problem,
+        rows.join("; ")
+    )))
+}
+
+fn with_lock<T>(
+    conn: &mut PooledConn,
+    f: impl FnOnce(&mut PooledConn) -> Result<T, String>,
+) -> Result<T, String> {
+    let acquired: Option<Option<i64>> = conn
+        .exec_first("SELECT GET_LOCK(?, ?)", (LOCK_NAME, LOCK_TIMEOUT_SECS))
+        .map_err(|e| format!("Failed to acquire migration lock: {}", e))?;
+
+    if acquired.flatten() != Some(1) {
+        return Err(format!("Timed out waiting for migration lock after {}s", LOCK_TIMEOUT_SECS));
+    }
+
+    let result = f(conn);
+
+    if let Err(e) = conn.exec_drop("DO RELEASE_LOCK(?)", (LOCK_NAME,)) {
+        println!("Failed to release migration lock: {}", e);
+    }
+
+    result
+}
+
+/// Applies every pending migration and returns the resulting schema version.
+///
+/// MySQL commits DDL implicitly, so each migration is recorded right after it
+/// runs; a failure leaves the database at the last fully applied version.
+pub fn migrate(conn: &mut PooledConn) -> Result<u32, String> {
+    ensure_version_table(conn).map_err(|e| format!("Failed to create schema_version table: {}", e))?;
+
+    with_lock(conn, |conn| {
+        let mut version = current_version(conn).map_err(|e| format!("Failed to read schema version: {}", e))?;
+
+        if version > latest_version() {
+            println!(
+                "Database schema version {} is newer than this build ({}); skipping migrations",
+                version,
+                latest_version()
+            );
+            return Ok(version);
+        }
+
+        let pending: Vec<&Migration> = MIGRATIONS.iter().filter(|m| m.version > version).collect();
+        for migration in pending {
+            if let Some(problem) = precheck(conn, migration)? {
+                return Err(problem);
+            }
+            println!("Applying migration {}", migration.name);
+            run_script(conn, migration.up).map_err(|e| format!("Migration {} failed: {}", migration.name, e))?;
+            conn.exec_drop(
+                "INSERT INTO schema_version (version, name) VALUES (?, ?)",
+                (migration.version, migration.name),
+            )
+            .map_err(|e| format!("Failed to record migration {}: {}", migration.name, e))?;
+            version = migration.version;
+        }
+
+        Ok(version)
+    })
+}
+
+/// Reverts applied migrations, newest first, until the schema is at `target`.
+pub fn rollback(conn: &mut PooledConn, target: u32) -> Result<u32, String> {
+    ensure_version_table(conn).map_err(|e| format!("Failed to create schema_version table: {}", e))?;
+
+    with_lock(conn, |conn| {
+        let version = current_version(conn).map_err(|e| format!("Failed to read schema version: {}", e))?;
+
+        for migration in MIGRATIONS.iter().rev().filter(|m| m.version > target && m.version <= version) {
+            println!("Reverting migration {}", migration.name);
+            run_script(conn, migration.down).map_err(|e| format!("Reverting {} failed: {}", migration.name, e))?;
+            conn.exec_drop("DELETE FROM schema_version WHERE version = ?", (migration.version,))
+                .map_err(|e| format!("Failed to unrecord migration {}: {}", migration.name, e))?;
+        }
+
+        current_version(conn).map_err(|e| format!("Failed to read schema version: {}", e))
+    })
+}
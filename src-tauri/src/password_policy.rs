@@ -0,0 +1,223 @@
+use mysql::prelude::*;
+use mysql::PooledConn;
+use serde::Serialize;
+
+use crate::config::PasswordConfig;
+use crate::error::{AppError, AppResult};
+use crate::hashing;
+
+/// A rule the new password broke. `rule` is stable for the frontend to switch
+/// on; `message` is shown to the user.
+#[derive(Clone, Debug, Serialize)]
+pub struct PolicyViolation {
+    pub rule: &'static str,
+    pub message: String,
+}
+
+impl PolicyViolation {
+    fn new(rule: &'static str, message: impl Into<String>) -> Self {
+        Self { rule, message: message.into() }
+    }
+}
+
+// Whether the rule is enabled, its name, its message and the characters that satisfy it
+type CharacterClass = (bool, &'static str, &'static str, fn(char) -> bool);
+
+/// What the password may not be equal to.
+pub struct PersonalInfo<'a> {
+    pub nim: &'a str,
+    pub initial: Option<&'a str>,
+    pub name: &'a str,
+}
+
+fn violations(config: &PasswordConfig, password: &str, personal: &PersonalInfo) -> Vec<PolicyViolation> {
+    let mut violations = Vec::new();
+
+    if password.chars().count() < config.min_length {
+        violations.push(PolicyViolation::new(
+            "min_length",
+            format!("be at least {} characters long", config.min_length),
+        ));
+    }
+
+    let classes: [CharacterClass; 4] = [
+        (config.require_lowercase, "lowercase", "contain a lowercase letter", char::is_lowercase),
+        (config.require_uppercase, "uppercase", "contain an uppercase letter", char::is_uppercase),
+        (config.require_digit, "digit", "contain a digit", |c| c.is_ascii_digit()),
+        (config.require_symbol, "symbol", "contain a symbol", |c| !c.is_alphanumeric() && !c.is_whitespace()),
+    ];
+    for (required, rule, description, matches) in classes {
+        if required && !password.chars().any(matches) {
+            violations.push(PolicyViolation::new(rule, description));
+        }
+    }
+
+    let normalized = password.trim().to_lowercase();
+    let personal_values = [Some(personal.nim), personal.initial, Some(personal.name)];
+    if personal_values
+        .iter()
+        .flatten()
+        .any(|value| !value.trim().is_empty() && value.trim().to_lowercase() == normalized)
+    {
+        violations.push(PolicyViolation::new("personal_info", "not be your NIM, initial or name"));
+    }
+
+    violations
+}
+
+// True when `password` matches the current hash or one of the last `config.history` hashes
+fn was_used_before(
+    conn: &mut PooledConn,
+    config: &PasswordConfig,
+    bn_number: &str,
+    current_hash: Option<&str>,
+    password: &str,
+) -> AppResult<bool> {
+    if config.history == 0 {
+        return Ok(false);
+    }
+
+    let mut hashes: Vec<String> = conn
+        .exec(
+            "SELECT password_hash FROM password_history WHERE bn_number = ? ORDER BY id DESC LIMIT ?",
+            (bn_number, config.history as u64),
+        )
+        .map_err(|e| AppError::database("Failed to query password history", e))?;
+    // Passwords set before password_history existed are only in users.password
+    hashes.extend(current_hash.map(String::from));
+
+    for hash in &hashes {
+        if hashing::verify(password, hash)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Checks a new password against every rule of the policy and fails with all
+/// the rules it breaks, listed in `details.violations`, so the user can fix
+/// them in one go.
+pub fn check(
+    conn: &mut PooledConn,
+    config: &PasswordConfig,
+    bn_number: &str,
+    current_hash: Option<&str>,
+    password: &str,
+    personal: &PersonalInfo,
+) -> AppResult<()> {
+    let mut violations = violations(config, password, personal);
+    if was_used_before(conn, config, bn_number, current_hash, password)? {
+        violations.push(PolicyViolation::new(
+            "reused",
+            format!("not be one of your last {} passwords", config.history),
+        ));
+    }
+
+    if violations.is_empty() {
+        return Ok(());
+    }
+
+    let rules: Vec<&str> = violations.iter().map(|violation| violation.message.as_str()).collect();
+    Err(AppError::validation(format!("The new password must {}", rules.join("; ")))
+        .with_details(serde_json::json!({ "violations": violations })))
+}
+
+pub fn remember(conn: &mut impl Queryable, bn_number: &str, password_hash: &str) -> AppResult<()> {
+    conn.exec_drop(
+        "INSERT INTO password_history (bn_number, password_hash) VALUES (?, ?)",
+        (bn_number, password_hash),
+    )
+    .map_err(|e| AppError::database("Failed to record password history", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERSONAL: PersonalInfo = PersonalInfo { nim: "2501234567", initial: Some("AB23-1"), name: "Budi Santoso" };
+
+    fn broken_rules(config: &PasswordConfig, password: &str) -> Vec<&'static str> {
+        violations(config, password, &PERSONAL).iter().map(|violation| violation.rule).collect()
+    }
+
+    // A policy whose only rule left is the personal info check
+    fn only_personal_info() -> PasswordConfig {
+        PasswordConfig {
+            min_length: 0,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            ..PasswordConfig::default()
+        }
+    }
+
+    #[test]
+    fn checks_each_rule_of_the_default_policy() {
+        let config = PasswordConfig::default();
+        for (password, expected) in [
+            ("Secret123", vec![]),
+            ("Sec123", vec!["min_length"]),
+            ("SECRET123", vec!["lowercase"]),
+            ("secret123", vec!["uppercase"]),
+            ("SecretPass", vec!["digit"]),
+            ("", vec!["min_length", "lowercase", "uppercase", "digit"]),
+        ] {
+            assert_eq!(broken_rules(&config, password), expected, "{:?}", password);
+        }
+    }
+
+    #[test]
+    fn counts_characters_rather_than_bytes() {
+        let config = PasswordConfig { min_length: 8, ..PasswordConfig::default() };
+
+        assert_eq!(broken_rules(&config, "Ääää1ää"), vec!["min_length"]);
+        assert!(broken_rules(&config, "Ääää1äää").is_empty());
+    }
+
+    #[test]
+    fn requires_a_symbol_when_enabled() {
+        let config = PasswordConfig { require_symbol: true, ..PasswordConfig::default() };
+        for (password, expected) in [
+            ("Secret123", vec!["symbol"]),
+            ("Secret 123", vec!["symbol"]),
+            ("Secret!123", vec![]),
+            ("Secret_123", vec![]),
+        ] {
+            assert_eq!(broken_rules(&config, password), expected, "{:?}", password);
+        }
+    }
+
+    #[test]
+    fn skips_disabled_rules() {
+        let config = only_personal_info();
+
+        assert!(broken_rules(&config, "").is_empty());
+        assert!(broken_rules(&config, "x").is_empty());
+    }
+
+    #[test]
+    fn rejects_personal_info_regardless_of_case_and_spaces() {
+        let config = only_personal_info();
+        for (password, rejected) in [
+            ("2501234567", true),
+            (" 2501234567 ", true),
+            ("ab23-1", true),
+            ("BUDI SANTOSO", true),
+            ("Budi", false),
+            ("25012345678", false),
+        ] {
+            assert_eq!(broken_rules(&config, password).contains(&"personal_info"), rejected, "{:?}", password);
+        }
+    }
+
+    #[test]
+    fn ignores_a_missing_or_blank_initial() {
+        let config = only_personal_info();
+        for initial in [None, Some(""), Some("  ")] {
+            let personal = PersonalInfo { initial, ..PERSONAL };
+            assert!(violations(&config, "", &personal).is_empty(), "{:?}", initial);
+            assert!(violations(&config, "  ", &personal).is_empty(), "{:?}", initial);
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use mysql::prelude::*;
+use mysql::{PooledConn, TxOpts};
+use serde::Serialize;
+
+use crate::audit;
+use crate::error::{AppError, AppResult};
+use crate::two_factor::{generate_code, hash_code};
+
+/// A reset code as shown to the admin, who passes it on to the user.
+#[derive(Clone, Debug, Serialize)]
+pub struct PasswordReset {
+    pub bn_number: String,
+    pub code: String,
+    /// Seconds since the Unix epoch.
+    pub expires_at: u64,
+}
+
+/// Issues a code that lets `bn_number` set a new password within
+/// `lifetime_minutes`. Codes issued to them before stop working.
+pub fn issue(
+    conn: &mut PooledConn,
+    bn_number: &str,
+    issued_by: &str,
+    machine: &str,
+    lifetime_minutes: u32,
+) -> AppResult<PasswordReset> {
+    let code = generate_code();
+
+    let mut transaction = conn
+        .start_transaction(TxOpts::default())
+        .map_err(|e| AppError::database("Failed to start transaction", e))?;
+    transaction
+        .exec_drop(
+            "UPDATE password_resets SET expires_at = NOW() WHERE bn_number = ? AND used_at IS NULL AND expires_at > NOW()",
+            (bn_number,),
+        )
+        .map_err(|e| AppError::database("Failed to revoke earlier reset codes", e))?;
+    transaction
+        .exec_drop(
+            "INSERT INTO password_resets (bn_number, code_hash, issued_by, expires_at) VALUES (?, ?, ?, NOW() + INTERVAL ? MINUTE)",
+            (bn_number, hash_code(&code), issued_by, lifetime_minutes),
+        )
+        .map_err(|e| AppError::database("Failed to store reset code", e))?;
+    let expires_at: Option<u64> = transaction
+        .exec_first(
+            "SELECT UNIX_TIMESTAMP(expires_at) FROM password_resets WHERE id = LAST_INSERT_ID()",
+            (),
+        )
+        .map_err(|e| AppError::database("Failed to read reset code", e))?;
+    audit::record(
+        &mut transaction,
+        issued_by,
+        machine,
+        "password_reset_issued",
+        Some(bn_number),
+        serde_json::json!({ "expires_in_minutes": lifetime_minutes }),
+    )?;
+    transaction.commit().map_err(|e| AppError::database("Failed to commit transaction", e))?;
+
+    Ok(PasswordReset {
+        bn_number: bn_number.to_string(),
+        code,
+        expires_at: expires_at.unwrap_or_default(),
+    })
+}
+
+/// The id of the unused, unexpired reset of `bn_number` matching `code`.
+pub fn find(conn: &mut PooledConn, bn_number: &str, code: &str) -> AppResult<Option<u64>> {
+    conn.exec_first(
+        "SELECT id FROM password_resets WHERE bn_number = ? AND code_hash = ? AND used_at IS NULL AND expires_at > NOW()",
+        (bn_number, hash_code(code)),
+    )
+    .map_err(|e| AppError::database("Failed to check reset code", e))
+}
+
+/// Marks the reset used inside the transaction that sets the new password, so
+/// the code cannot be spent twice even by two windows at once.
+pub fn consume(conn: &mut impl Queryable, id: u64) -> AppResult<()> {
+    let updated = conn
+        .exec_iter(
+            "UPDATE password_resets SET used_at = NOW() WHERE id = ? AND used_at IS NULL AND expires_at > NOW()",
+            (id,),
+        )
+        .map_err(|e| AppError::database("Failed to use reset code", e))?
+        .affected_rows();
+
+    if updated == 0 {
+        return Err(AppError::validation("The reset code has already been used or has expired"));
+    }
+    Ok(())
+}
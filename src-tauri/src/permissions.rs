@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::{impersonation, suspension};
+use crate::{AppState, CurrentUser};
+
+/// Role that may run a command regardless of the caller's actual role.
+pub const ANY_ROLE: &str = "*";
+
+/// Commands that run without a session. Every other command needs a valid
+/// token.
+pub const PUBLIC_COMMANDS: &[&str] = &["login", "restore_session", "verify_two_factor", "reset_password", "get_system_health"];
+
+/// Commands still allowed while the logged-in user has to replace a default
+/// password or enroll in two-factor authentication.
+pub const ACCOUNT_SETUP_COMMANDS: &[&str] = &[
+    "logout",
+    "get_current_user",
+    "change_password",
+    "begin_two_factor_enrollment",
+    "confirm_two_factor_enrollment",
+];
+
+/// Maps a Tauri command name to the roles allowed to run it.
+///
+/// Commands without an entry are denied, so forgetting to list a new
+/// privileged command fails closed instead of open.
+#[derive(Clone, Debug)]
+pub struct Permissions {
+    rules: HashMap<String, Vec<String>>,
+}
+
+impl Permissions {
+    pub fn new(rules: HashMap<String, Vec<String>>) -> Self {
+        Self { rules }
+    }
+
+    pub fn allows(&self, command: &str, role: &str) -> bool {
+        match self.rules.get(command) {
+            Some(roles) => roles.iter().any(|allowed| allowed == ANY_ROLE || allowed == role),
+            None => false,
+        }
+    }
+
+    /// The default rules with `overrides` (from the `[permissions]` config
+    /// table) replacing the roles of the commands they name.
+    pub fn with_overrides(overrides: HashMap<String, Vec<String>>) -> Self {
+        let mut permissions = Self::default();
+        permissions.rules.extend(overrides);
+        permissions
+    }
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        let rules = [
+            ("update_user_role", vec!["Exam Coordinator"]),
+            ("allocate_exam", vec!["Exam Coordinator"]),
+            ("allocate_exam_split", vec!["Exam Coordinator"]),
+            ("update_transaction_proctor", vec!["Exam Coordinator"]),
+            ("get_transaction_participants", vec!["Exam Coordinator", "Subject Development", "Assistant"]),
+            ("get_exam_participants", vec!["Exam Coordinator", "Subject Development", "Assistant"]),
+            ("get_locked_accounts", vec!["Exam Coordinator"]),
+            ("clear_account_lockout", vec!["Exam Coordinator"]),
+            ("get_two_factor_roles", vec!["Exam Coordinator"]),
+            ("set_two_factor_roles", vec!["Exam Coordinator"]),
+            ("issue_password_reset", vec!["Exam Coordinator"]),
+            ("get_audit_log", vec!["Exam Coordinator"]),
+            ("suspend_account", vec!["Exam Coordinator"]),
+            ("reinstate_account", vec!["Exam Coordinator"]),
+            ("get_suspended_accounts", vec!["Exam Coordinator"]),
+            ("start_impersonation", vec!["Exam Coordinator"]),
+            ("get_sync_runs", vec!["Exam Coordinator"]),
+            ("trigger_sync", vec!["Exam Coordinator"]),
+            // Students are further limited to their own NIM
+            ("refresh_user_by_nim", vec![ANY_ROLE]),
+            ("refresh_enrollment_by_nim", vec![ANY_ROLE]),
+            ("refresh_user_by_initial", vec!["Exam Coordinator", "Subject Development", "Assistant"]),
+            ("refresh_user_with_role", vec!["Exam Coordinator"]),
+            ("refresh_room_by_campus", vec!["Exam Coordinator"]),
+            ("refresh_subject_by_code", vec!["Exam Coordinator", "Subject Development"]),
+        ];
+
+        Self::new(
+            rules
+                .into_iter()
+                .map(|(command, roles)| (command.to_string(), roles.into_iter().map(String::from).collect()))
+                .collect(),
+        )
+    }
+}
+
+/// Runs before each command. Rejects commands outside `PUBLIC_COMMANDS`
+/// without a live session, commands that write while an admin views the app
+/// as another user, and every command outside `ACCOUNT_SETUP_COMMANDS` while
+/// the user of `token` still has to change a default password or enroll in
+/// two-factor.
+pub fn require_account_ready(state: &AppState, command: &str, token: Option<&str>) -> AppResult<()> {
+    if PUBLIC_COMMANDS.contains(&command) {
+        return Ok(());
+    }
+    let user = state.sessions.resolve(token.ok_or_else(AppError::unauthenticated)?)?;
+
+    if let Some(admin) = &user.impersonated_by {
+        if !impersonation::allows(command) {
+            println!("Blocked {} for {} viewing as {}", command, admin, user.bn_number);
+            return Err(AppError::new(
+                ErrorCode::Forbidden,
+                format!("{} is not allowed while viewing as another user", command),
+            )
+            .with_details(serde_json::json!({ "command": command, "impersonating": user.bn_number })));
+        }
+    }
+
+    if ACCOUNT_SETUP_COMMANDS.contains(&command) {
+        return Ok(());
+    }
+    if user.must_change_password {
+        return Err(AppError::new(
+            ErrorCode::PasswordChangeRequired,
+            "Change your default password before continuing",
+        )
+        .with_details(serde_json::json!({ "command": command })));
+    }
+    if user.must_enroll_two_factor {
+        return Err(AppError::new(
+            ErrorCode::TwoFactorEnrollmentRequired,
+            "Set up two-factor authentication before continuing",
+        )
+        .with_details(serde_json::json!({ "command": command })));
+    }
+    Ok(())
+}
+
+/// Returns the user logged in under `token`. The session of a user who has
+/// been suspended since logging in is closed. While MySQL is unreachable the
+/// suspension cannot be checked and the session stands, so commands with a
+/// fallback such as `get_all_users` keep working.
+pub fn authenticate(state: &AppState, token: &str) -> AppResult<CurrentUser> {
+    let user = state.sessions.resolve(token)?;
+
+    // While impersonating, it is the admin's own account that matters
+    let real_user = user.impersonated_by.as_deref().unwrap_or(&user.bn_number);
+    match state.conn().and_then(|mut conn| suspension::check(&mut conn, real_user)) {
+        Ok(()) => {}
+        Err(e) if e.code == ErrorCode::DatabaseUnavailable => {
+            println!("Could not check whether {} is suspended: {}", real_user, e);
+        }
+        Err(e) => {
+            if e.code == ErrorCode::AccountSuspended {
+                println!("Closing session of suspended account {}", real_user);
+                state.sessions.remove(token)?;
+            }
+            return Err(e);
+        }
+    }
+
+    Ok(user)
+}
+
+/// Returns the user logged in under `token` when their role may run `command`.
+pub fn authorize(state: &AppState, token: &str, command: &str) -> AppResult<CurrentUser> {
+    let user = authenticate(state, token)?;
+
+    if !state.permissions.allows(command, &user.role) {
+        println!("Forbidden: {} ({}) tried to run {}", user.bn_number, user.role, command);
+        return Err(AppError::new(
+            ErrorCode::Forbidden,
+            format!("Forbidden: role '{}' is not allowed to run {}", user.role, command),
+        )
+        .with_details(serde_json::json!({ "command": command, "role": user.role })));
+    }
+
+    Ok(user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_the_default_rules_per_role() {
+        let permissions = Permissions::default();
+        for (command, role, allowed) in [
+            ("allocate_exam", "Exam Coordinator", true),
+            ("allocate_exam", "Subject Development", false),
+            ("allocate_exam", "Student", false),
+            ("get_exam_participants", "Assistant", true),
+            ("get_exam_participants", "Student", false),
+            ("refresh_subject_by_code", "Subject Development", true),
+            ("refresh_subject_by_code", "Assistant", false),
+            ("refresh_enrollment_by_nim", "Student", true),
+            ("refresh_user_by_nim", "Anything at all", true),
+        ] {
+            assert_eq!(permissions.allows(command, role), allowed, "{} as {}", command, role);
+        }
+    }
+
+    #[test]
+    fn denies_commands_without_a_rule() {
+        let permissions = Permissions::default();
+
+        assert!(!permissions.allows("drop_everything", "Exam Coordinator"));
+        assert!(!permissions.allows("drop_everything", ANY_ROLE));
+    }
+
+    #[test]
+    fn overrides_replace_the_default_roles() {
+        let overrides = HashMap::from([
+            ("allocate_exam".to_string(), vec!["Subject Development".to_string()]),
+            ("get_audit_log".to_string(), vec![ANY_ROLE.to_string()]),
+        ]);
+        let permissions = Permissions::with_overrides(overrides);
+
+        assert!(permissions.allows("allocate_exam", "Subject Development"));
+        assert!(!permissions.allows("allocate_exam", "Exam Coordinator"));
+        assert!(permissions.allows("get_audit_log", "Student"));
+        // Commands not overridden keep their defaults
+        assert!(permissions.allows("trigger_sync", "Exam Coordinator"));
+        assert!(!permissions.allows("trigger_sync", "Student"));
+    }
+}
@@ -0,0 +1,158 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+
+use crate::clock;
+use crate::error::{AppError, AppResult};
+use crate::CurrentUser;
+
+const SESSION_FILE: &str = "session.bin";
+const KEY_FILE: &str = "session.key";
+const NONCE_LEN: usize = 24;
+
+#[derive(Serialize, Deserialize)]
+struct RememberedSession {
+    token: String,
+    user: CurrentUser,
+    /// Seconds since the Unix epoch after which the file is ignored.
+    expires_at: u64,
+}
+
+#[cfg(unix)]
+fn restrict_to_owner(path: &Path) -> AppResult<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+        .map_err(|e| AppError::internal(format!("Failed to protect session key: {}", e)))
+}
+
+// Replaces the ACL inherited from the app data directory with full control
+// for the current user alone
+#[cfg(windows)]
+fn restrict_to_owner(path: &Path) -> AppResult<()> {
+    use std::os::windows::process::CommandExt;
+    // Keeps icacls from flashing a console window
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+    let user = std::env::var("USERNAME").map_err(|_| AppError::internal("Failed to protect session key: USERNAME is not set"))?;
+    let status = std::process::Command::new("icacls")
+        .arg(path)
+        .args(["/inheritance:r", "/grant:r"])
+        .arg(format!("{}:F", user))
+        .creation_flags(CREATE_NO_WINDOW)
+        .status()
+        .map_err(|e| AppError::internal(format!("Failed to protect session key: {}", e)))?;
+    if !status.success() {
+        return Err(AppError::internal(format!("Failed to protect session key: icacls exited with {}", status)));
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn restrict_to_owner(_path: &Path) -> AppResult<()> {
+    Ok(())
+}
+
+/// The "remember me" login of this machine, kept across restarts in an
+/// encrypted file in the app data directory.
+///
+/// The key sits in a separate file that only the current OS user may read
+/// (administrators can still take it on Windows), so a copied session file is
+/// useless on its own.
+pub struct RememberMe {
+    dir: Option<PathBuf>,
+    lifetime: Duration,
+}
+
+impl RememberMe {
+    /// `dir` is `None` when the platform has no app data directory, which
+    /// turns remember-me off. A zero `lifetime` turns it off too.
+    pub fn new(dir: Option<PathBuf>, lifetime: Duration) -> Self {
+        Self { dir, lifetime }
+    }
+
+    fn enabled_dir(&self) -> Option<&PathBuf> {
+        self.dir.as_ref().filter(|_| !self.lifetime.is_zero())
+    }
+
+    fn cipher(dir: &PathBuf) -> AppResult<XChaCha20Poly1305> {
+        let path = dir.join(KEY_FILE);
+        let key = match fs::read(&path) {
+            Ok(key) if key.len() == 32 => key,
+            _ => {
+                let key = XChaCha20Poly1305::generate_key(&mut OsRng).to_vec();
+                fs::create_dir_all(dir).map_err(|e| AppError::internal(format!("Failed to create {}: {}", dir.display(), e)))?;
+                fs::write(&path, &key).map_err(|e| AppError::internal(format!("Failed to write session key: {}", e)))?;
+                if let Err(e) = restrict_to_owner(&path) {
+                    // An unprotected key must not be picked up by the next load
+                    let _ = fs::remove_file(&path);
+                    return Err(e);
+                }
+                key
+            }
+        };
+        Ok(XChaCha20Poly1305::new(Key::from_slice(&key)))
+    }
+
+    pub fn save(&self, token: &str, user: &CurrentUser) -> AppResult<()> {
+        let Some(dir) = self.enabled_dir() else {
+            return Ok(());
+        };
+
+        let session = RememberedSession {
+            token: token.to_string(),
+            user: user.clone(),
+            expires_at: clock::now() + self.lifetime.as_secs(),
+        };
+        let plaintext = serde_json::to_vec(&session).map_err(|e| AppError::internal(format!("Failed to encode session: {}", e)))?;
+
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = Self::cipher(dir)?
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| AppError::internal("Failed to encrypt session"))?;
+
+        let contents: Vec<u8> = nonce.iter().copied().chain(ciphertext).collect();
+        fs::write(dir.join(SESSION_FILE), contents).map_err(|e| AppError::internal(format!("Failed to write session file: {}", e)))
+    }
+
+    /// The remembered token and user, unless there is none, it expired or it
+    /// cannot be decrypted. Unusable files are deleted.
+    pub fn load(&self) -> Option<(String, CurrentUser)> {
+        let dir = self.enabled_dir()?;
+        let contents = fs::read(dir.join(SESSION_FILE)).ok()?;
+
+        let session = if contents.len() > NONCE_LEN {
+            Self::cipher(dir)
+                .ok()
+                .and_then(|cipher| cipher.decrypt(XNonce::from_slice(&contents[..NONCE_LEN]), &contents[NONCE_LEN..]).ok())
+                .and_then(|plaintext| serde_json::from_slice::<RememberedSession>(&plaintext).ok())
+        } else {
+            None
+        };
+
+        match session {
+            Some(session) if session.expires_at > clock::now() => Some((session.token, session.user)),
+            _ => {
+                println!("Discarding unusable remembered session");
+                self.forget(None);
+                None
+            }
+        }
+    }
+
+    /// Deletes the file, or only when it belongs to `token` if one is given.
+    pub fn forget(&self, token: Option<&str>) {
+        let Some(dir) = self.dir.as_ref() else {
+            return;
+        };
+        if let Some(token) = token {
+            if self.load().map(|(remembered, _)| remembered != token).unwrap_or(true) {
+                return;
+            }
+        }
+        let _ = fs::remove_file(dir.join(SESSION_FILE));
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::Serialize;
+
+use crate::clock::{self, Clock};
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::CurrentUser;
+
+const TOKEN_BYTES: usize = 32;
+
+/// A random hex token that cannot be guessed.
+pub fn new_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// A logged-in user behind an opaque token. Times are seconds since the Unix epoch.
+#[derive(Clone, Debug, Serialize)]
+pub struct Session {
+    /// The effective user, who commands run as.
+    pub user: CurrentUser,
+    /// The admin who is viewing the app as `user`, if any.
+    pub impersonator: Option<CurrentUser>,
+    pub created_at: u64,
+    pub last_activity: u64,
+    pub expires_at: u64,
+}
+
+/// Every live session of this process. Each window logs in on its own and
+/// passes its token to the commands it runs, so a proctor kiosk and an admin
+/// window can be open side by side.
+pub struct SessionStore {
+    sessions: Mutex<HashMap<String, Session>>,
+    idle_timeout: Duration,
+    clock: Clock,
+}
+
+impl SessionStore {
+    pub fn new(idle_timeout: Duration) -> Self {
+        Self::with_clock(idle_timeout, Box::new(clock::now))
+    }
+
+    /// A store that reads the time from `clock`, so expiry can be tested.
+    pub fn with_clock(idle_timeout: Duration, clock: Clock) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            idle_timeout,
+            clock,
+        }
+    }
+
+    fn expiry(&self, last_activity: u64) -> u64 {
+        last_activity + self.idle_timeout.as_secs()
+    }
+
+    /// Starts a session for `user` and returns its token.
+    pub fn create(&self, user: CurrentUser) -> AppResult<String> {
+        let token = new_token();
+        self.restore(&token, user)?;
+        Ok(token)
+    }
+
+    /// Starts a session under a token issued earlier, e.g. by a remembered
+    /// login from a previous run. A live session with that token is kept.
+    pub fn restore(&self, token: &str, user: CurrentUser) -> AppResult<()> {
+        let now = (self.clock)();
+        self.sessions.lock()?.entry(token.to_string()).or_insert_with(|| Session {
+            user,
+            impersonator: None,
+            created_at: now,
+            last_activity: now,
+            expires_at: self.expiry(now),
+        });
+        Ok(())
+    }
+
+    /// Returns the user behind `token` and counts the call as activity. A
+    /// session idle for longer than the timeout is refused; `expire_idle`
+    /// removes it later.
+    pub fn resolve(&self, token: &str) -> AppResult<CurrentUser> {
+        let mut sessions = self.sessions.lock()?;
+        let now = (self.clock)();
+
+        let session = sessions.get_mut(token).ok_or_else(AppError::unauthenticated)?;
+        if session.expires_at <= now {
+            return Err(AppError::new(ErrorCode::SessionExpired, "Your session expired, please log in again"));
+        }
+
+        session.last_activity = now;
+        session.expires_at = self.expiry(now);
+        Ok(session.user.clone())
+    }
+
+    /// Applies `change` to the user of a live session.
+    pub fn update(&self, token: &str, change: impl FnOnce(&mut CurrentUser)) -> AppResult<()> {
+        let mut sessions = self.sessions.lock()?;
+        let session = sessions.get_mut(token).ok_or_else(AppError::unauthenticated)?;
+        change(&mut session.user);
+        Ok(())
+    }
+
+    /// Makes the session run as `target` until `stop_impersonating`.
+    pub fn impersonate(&self, token: &str, mut target: CurrentUser) -> AppResult<()> {
+        let mut sessions = self.sessions.lock()?;
+        let session = sessions.get_mut(token).ok_or_else(AppError::unauthenticated)?;
+        if session.impersonator.is_some() {
+            return Err(AppError::validation("Stop viewing as the current user first"));
+        }
+
+        target.impersonated_by = Some(session.user.bn_number.clone());
+        session.impersonator = Some(std::mem::replace(&mut session.user, target));
+        Ok(())
+    }
+
+    /// Switches the session back to the admin and returns the admin and the
+    /// user they were viewing as.
+    pub fn stop_impersonating(&self, token: &str) -> AppResult<(CurrentUser, CurrentUser)> {
+        let mut sessions = self.sessions.lock()?;
+        let session = sessions.get_mut(token).ok_or_else(AppError::unauthenticated)?;
+        let admin = session
+            .impersonator
+            .take()
+            .ok_or_else(|| AppError::validation("You are not viewing as another user"))?;
+
+        let target = std::mem::replace(&mut session.user, admin.clone());
+        Ok((admin, target))
+    }
+
+    /// Drops every session idle for longer than the timeout and returns their tokens.
+    pub fn expire_idle(&self) -> AppResult<Vec<(String, Session)>> {
+        let mut sessions = self.sessions.lock()?;
+        let now = (self.clock)();
+
+        let expired: Vec<String> = sessions
+            .iter()
+            .filter(|(_, session)| session.expires_at <= now)
+            .map(|(token, _)| token.clone())
+            .collect();
+
+        Ok(expired
+            .into_iter()
+            .filter_map(|token| sessions.remove(&token).map(|session| (token, session)))
+            .collect())
+    }
+
+    /// Ends one session; other windows stay logged in.
+    pub fn remove(&self, token: &str) -> AppResult<Option<Session>> {
+        Ok(self.sessions.lock()?.remove(token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    const IDLE_TIMEOUT_SECS: u64 = 60;
+
+    // A store whose clock only moves when the test sets it
+    fn store() -> (SessionStore, Arc<AtomicU64>) {
+        let time = Arc::new(AtomicU64::new(1_000));
+        let clock = Arc::clone(&time);
+        let store =
+            SessionStore::with_clock(Duration::from_secs(IDLE_TIMEOUT_SECS), Box::new(move || clock.load(Ordering::SeqCst)));
+        (store, time)
+    }
+
+    fn user(bn_number: &str) -> CurrentUser {
+        CurrentUser {
+            bn_number: bn_number.to_string(),
+            nim: "2501234567".to_string(),
+            name: "Budi Santoso".to_string(),
+            major: "Computer Science".to_string(),
+            role: "Student".to_string(),
+            initial: None,
+            login_type: "nim".to_string(),
+            must_change_password: false,
+            must_enroll_two_factor: false,
+            impersonated_by: None,
+        }
+    }
+
+    #[test]
+    fn expires_idle_sessions() {
+        let (store, time) = store();
+        let token = store.create(user("BN001")).unwrap();
+
+        time.store(1_000 + IDLE_TIMEOUT_SECS - 1, Ordering::SeqCst);
+        assert!(store.expire_idle().unwrap().is_empty());
+
+        time.store(1_000 + IDLE_TIMEOUT_SECS, Ordering::SeqCst);
+        assert_eq!(store.resolve(&token).unwrap_err().code, ErrorCode::SessionExpired);
+
+        let expired = store.expire_idle().unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].0, token);
+        assert_eq!(store.resolve(&token).unwrap_err().code, ErrorCode::Unauthenticated);
+    }
+
+    #[test]
+    fn resolving_keeps_the_session_alive() {
+        let (store, time) = store();
+        let token = store.create(user("BN001")).unwrap();
+
+        // Each call lands before the timeout since the previous one, but well after the first
+        for step in 1..=3 {
+            time.store(1_000 + step * (IDLE_TIMEOUT_SECS - 10), Ordering::SeqCst);
+            assert_eq!(store.resolve(&token).unwrap().bn_number, "BN001", "step {}", step);
+        }
+
+        time.store(1_000 + 3 * (IDLE_TIMEOUT_SECS - 10) + IDLE_TIMEOUT_SECS, Ordering::SeqCst);
+        assert_eq!(store.resolve(&token).unwrap_err().code, ErrorCode::SessionExpired);
+    }
+
+    #[test]
+    fn restores_a_remembered_token() {
+        let (store, _) = store();
+        assert_eq!(store.resolve("remembered").unwrap_err().code, ErrorCode::Unauthenticated);
+
+        store.restore("remembered", user("BN001")).unwrap();
+        assert_eq!(store.resolve("remembered").unwrap().bn_number, "BN001");
+
+        // A second window restoring the same token joins the live session
+        store.restore("remembered", user("BN002")).unwrap();
+        assert_eq!(store.resolve("remembered").unwrap().bn_number, "BN001");
+    }
+}
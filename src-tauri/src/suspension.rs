@@ -0,0 +1,102 @@
+use mysql::prelude::*;
+use mysql::{PooledConn, TxOpts};
+use serde::Serialize;
+
+use crate::audit;
+use crate::error::{AppError, AppResult, ErrorCode};
+
+// Matches users whose suspension is in force; one with a past end date is active again
+const SUSPENDED: &str = "active = FALSE AND (suspended_until IS NULL OR suspended_until > NOW())";
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SuspendedAccount {
+    pub bn_number: String,
+    pub name: String,
+    pub reason: Option<String>,
+    /// Seconds since the Unix epoch; `None` until reinstated.
+    pub suspended_until: Option<u64>,
+}
+
+/// Fails with `AccountSuspended` when `bn_number` is suspended.
+pub fn check(conn: &mut PooledConn, bn_number: &str) -> AppResult<()> {
+    let row: Option<(Option<String>, Option<u64>)> = conn
+        .exec_first(
+            format!("SELECT suspension_reason, UNIX_TIMESTAMP(suspended_until) FROM users WHERE bn_number = ? AND {SUSPENDED}"),
+            (bn_number,),
+        )
+        .map_err(|e| AppError::database("Failed to check account suspension", e))?;
+
+    let Some((reason, suspended_until)) = row else {
+        return Ok(());
+    };
+    Err(AppError::new(ErrorCode::AccountSuspended, "This account has been suspended")
+        .with_details(serde_json::json!({ "reason": reason, "suspended_until": suspended_until })))
+}
+
+pub fn suspended_accounts(conn: &mut PooledConn) -> AppResult<Vec<SuspendedAccount>> {
+    conn.query_map(
+        format!("SELECT bn_number, name, suspension_reason, UNIX_TIMESTAMP(suspended_until) FROM users WHERE {SUSPENDED} ORDER BY name"),
+        |(bn_number, name, reason, suspended_until)| SuspendedAccount { bn_number, name, reason, suspended_until },
+    )
+    .map_err(|e| AppError::database("Failed to query suspended accounts", e))
+}
+
+enum Change {
+    Suspend { until: Option<u64> },
+    Reinstate,
+}
+
+// Sets the suspension columns of `bn_number` and records the change and its reason in the audit log
+fn apply(conn: &mut PooledConn, bn_number: &str, admin: &str, machine: &str, change: Change, reason: &str) -> AppResult<()> {
+    let (action, active, suspended_until, stored_reason) = match change {
+        Change::Suspend { until } => ("account_suspended", false, until, Some(reason)),
+        Change::Reinstate => ("account_reinstated", true, None, None),
+    };
+
+    let mut transaction = conn
+        .start_transaction(TxOpts::default())
+        .map_err(|e| AppError::database("Failed to start transaction", e))?;
+    let exists: Option<String> = transaction
+        .exec_first("SELECT bn_number FROM users WHERE bn_number = ? FOR UPDATE", (bn_number,))
+        .map_err(|e| AppError::database("Failed to execute query", e))?;
+    if exists.is_none() {
+        return Err(AppError::not_found(format!("User {} not found", bn_number)));
+    }
+    transaction
+        .exec_drop(
+            "UPDATE users SET active = ?, suspended_until = FROM_UNIXTIME(?), suspension_reason = ? WHERE bn_number = ?",
+            (active, suspended_until, stored_reason, bn_number),
+        )
+        .map_err(|e| AppError::database("Failed to update account", e))?;
+    audit::record(
+        &mut transaction,
+        admin,
+        machine,
+        action,
+        Some(bn_number),
+        serde_json::json!({ "reason": reason, "suspended_until": suspended_until }),
+    )?;
+    transaction.commit().map_err(|e| AppError::database("Failed to commit transaction", e))
+}
+
+/// Suspends `bn_number` until `suspended_until` (seconds since the Unix
+/// epoch), or until reinstated when `None`.
+pub fn suspend(
+    conn: &mut PooledConn,
+    bn_number: &str,
+    admin: &str,
+    machine: &str,
+    reason: &str,
+    suspended_until: Option<u64>,
+) -> AppResult<()> {
+    apply(conn, bn_number, admin, machine, Change::Suspend { until: suspended_until }, reason)
+}
+
+pub fn reinstate(conn: &mut PooledConn, bn_number: &str, admin: &str, machine: &str, reason: &str) -> AppResult<()> {
+    match check(conn, bn_number) {
+        Ok(()) => return Err(AppError::validation(format!("{} is not suspended", bn_number))),
+        Err(e) if e.code != ErrorCode::AccountSuspended => return Err(e),
+        Err(_) => {}
+    }
+    apply(conn, bn_number, admin, machine, Change::Reinstate, reason)
+}
@@ -0,0 +1,721 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use mysql::prelude::*;
+use mysql::{Params, Pool, PooledConn, Transaction, TxOpts, Value};
+use serde::Serialize;
+
+use crate::config::{ConflictPolicy, GraphqlConfig, SyncConfig};
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::{fetch_enrollments, fetch_rooms, fetch_subjects, fetch_users, Enrollment, Room, Subject, User};
+
+/// A local table mirrored from the GraphQL API.
+struct Table {
+    name: &'static str,
+    key: &'static [&'static str],
+    /// Columns copied from upstream besides the key.
+    columns: &'static [&'static str],
+    /// Columns that reference this table's (single column) key. A row deleted
+    /// upstream is kept while exams still use it.
+    referenced_by: &'static [(&'static str, &'static str)],
+}
+
+const USERS: Table = Table {
+    name: "users",
+    key: &["nim"],
+    columns: &["bn_number", "name", "major", "role", "initial"],
+    referenced_by: &[("transaction_detail", "nim")],
+};
+
+const ROOMS: Table = Table {
+    name: "rooms",
+    key: &["room_number"],
+    columns: &["room_capacity", "campus"],
+    referenced_by: &[("transaction_header", "room_number")],
+};
+
+const SUBJECTS: Table = Table {
+    name: "subjects",
+    key: &["subject_code"],
+    columns: &["subject_name"],
+    referenced_by: &[("transaction_header", "subject_code")],
+};
+
+const ENROLLMENTS: Table = Table {
+    name: "enrollments",
+    key: &["subject_code", "class_code", "nim"],
+    columns: &[],
+    referenced_by: &[],
+};
+
+const TABLES: [&Table; 4] = [&USERS, &ROOMS, &SUBJECTS, &ENROLLMENTS];
+
+/// MySQL named lock held for a whole sync run.
+const LOCK_NAME: &str = "sr_exam_sync";
+
+/// Used for columns edited locally that `SyncConfig` does not mention.
+/// Everything else follows upstream.
+const DEFAULT_POLICIES: &[(&str, ConflictPolicy)] = &[
+    // Changed by update_user_role
+    ("users.role", ConflictPolicy::Local),
+];
+
+/// True for "table.column" names of columns that sync writes.
+pub fn is_mirrored(column: &str) -> bool {
+    TABLES
+        .iter()
+        .any(|table| table.columns.iter().any(|name| format!("{}.{}", table.name, name) == column))
+}
+
+fn row_key(key: &[impl AsRef<str>]) -> String {
+    serde_json::to_string(&key.iter().map(AsRef::as_ref).collect::<Vec<&str>>()).unwrap_or_default()
+}
+
+/// Records that a synced column was changed in this app, so the next sync
+/// applies the conflict policy instead of overwriting it. `base_value` is
+/// the value before the change; a column edited again keeps its first one.
+pub fn record_local_edit(
+    conn: &mut impl Queryable,
+    table: &str,
+    key: &[&str],
+    column: &str,
+    base_value: Option<&str>,
+    edited_by: &str,
+) -> AppResult<()> {
+    conn.exec_drop(
+        r"INSERT INTO local_edits (table_name, row_key, column_name, base_value, edited_by)
+        VALUES (?, ?, ?, ?, ?)
+        ON DUPLICATE KEY UPDATE edited_by = VALUES(edited_by), edited_at = NOW()",
+        (table, row_key(key), column, base_value, edited_by),
+    )
+    .map_err(|e| AppError::database("Failed to record local edit", e))
+}
+
+// Local edits by table, row key and column, with their base values
+type Edits = HashMap<(String, String, String), Option<String>>;
+
+struct Context<'a> {
+    config: &'a SyncConfig,
+    edits: Edits,
+}
+
+impl Context<'_> {
+    fn policy(&self, table: &Table, column: &str) -> ConflictPolicy {
+        let name = format!("{}.{}", table.name, column);
+        self.config
+            .conflicts
+            .get(&name)
+            .copied()
+            .or_else(|| DEFAULT_POLICIES.iter().find(|(default, _)| *default == name).map(|(_, policy)| *policy))
+            .unwrap_or(ConflictPolicy::Upstream)
+    }
+}
+
+// Rows by key, every value as text so all tables diff the same way
+type Key = Vec<String>;
+type Rows = BTreeMap<Key, Vec<Option<String>>>;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TableReport {
+    pub table: &'static str,
+    pub inserted: usize,
+    pub updated: usize,
+    pub deleted: usize,
+    /// Rows deleted upstream but still used by exams, and upstream rows that
+    /// reference users or subjects that do not exist.
+    pub skipped: usize,
+    /// Locally edited fields left alone although upstream has another value.
+    pub kept_local: usize,
+}
+
+impl TableReport {
+    fn new(table: &Table) -> Self {
+        Self { table: table.name, inserted: 0, updated: 0, deleted: 0, skipped: 0, kept_local: 0 }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SyncReport {
+    pub run_id: u64,
+    pub tables: Vec<TableReport>,
+}
+
+impl SyncReport {
+    pub fn totals(&self) -> (usize, usize, usize) {
+        self.tables.iter().fold((0, 0, 0), |(inserted, updated, deleted), table| {
+            (inserted + table.inserted, updated + table.updated, deleted + table.deleted)
+        })
+    }
+
+    pub fn summary(&self) -> String {
+        let (inserted, updated, deleted) = self.totals();
+        format!(
+            "Synced users, rooms, subjects and enrollments: {} inserted, {} updated, {} deleted",
+            inserted, updated, deleted
+        )
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SyncRun {
+    pub id: u64,
+    /// Seconds since the Unix epoch.
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+    /// "running", "succeeded" or "failed".
+    pub status: String,
+    /// "startup", "schedule" or the bn_number of the admin who asked for it.
+    pub triggered_by: String,
+    pub report: serde_json::Value,
+    pub error: Option<String>,
+}
+
+/// Payload of `sync://progress`.
+#[derive(Clone, Debug, Serialize)]
+pub struct Progress {
+    pub run_id: u64,
+    /// "started", "fetching", "applying" or "applied".
+    pub stage: &'static str,
+    pub message: String,
+}
+
+/// Receives progress while a sync runs.
+pub type ProgressFn<'a> = &'a (dyn Fn(Progress) + Sync);
+
+/// Makes sure only one sync runs at a time in this process; `run` also takes a
+/// MySQL lock so other machines sharing the database wait their turn.
+#[derive(Default)]
+pub struct SyncLock {
+    running: AtomicBool,
+}
+
+/// Held for the duration of a sync; releases the lock when dropped.
+pub struct Running<'a>(&'a AtomicBool);
+
+impl SyncLock {
+    /// `None` when a sync is already running.
+    pub fn try_start(&self) -> Option<Running<'_>> {
+        self.running
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .ok()
+            .map(|_| Running(&self.running))
+    }
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+struct Remote {
+    users: Vec<User>,
+    rooms: Vec<Room>,
+    subjects: Vec<Subject>,
+    enrollments: Vec<Enrollment>,
+}
+
+async fn fetch(graphql: &GraphqlConfig, report: impl Fn(&'static str, String)) -> AppResult<Remote> {
+    report("fetching", "Fetching users".to_string());
+    let users = fetch_users(graphql).await?;
+    report("fetching", "Fetching rooms".to_string());
+    let rooms = fetch_rooms(graphql).await?;
+    report("fetching", "Fetching subjects".to_string());
+    let subjects = fetch_subjects(graphql).await?;
+    report("fetching", "Fetching enrollments".to_string());
+    let enrollments = fetch_enrollments(graphql).await?;
+
+    Ok(Remote { users, rooms, subjects, enrollments })
+}
+
+// A key column and its value, limiting a refresh to part of a table
+type Scope<'a> = Option<(&'static str, &'a str)>;
+
+fn load(tx: &mut Transaction, table: &Table, scope: Scope) -> AppResult<Rows> {
+    let columns: Vec<String> = table
+        .key
+        .iter()
+        .chain(table.columns)
+        .map(|column| format!("CAST({} AS CHAR)", column))
+        .collect();
+    let mut query = format!("SELECT {} FROM {}", columns.join(", "), table.name);
+    let mut values = Vec::new();
+    if let Some((column, value)) = scope {
+        query.push_str(&format!(" WHERE {} = ?", column));
+        values.push(Some(value.to_string()));
+    }
+
+    let rows: Vec<mysql::Row> = tx
+        .exec(query, params(values))
+        .map_err(|e| AppError::database(&format!("Failed to read {}", table.name), e))?;
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let mut values: Vec<Option<String>> = row.unwrap().into_iter().map(mysql::from_value).collect();
+            let columns = values.split_off(table.key.len());
+            (values.into_iter().map(Option::unwrap_or_default).collect(), columns)
+        })
+        .collect())
+}
+
+fn where_key(table: &Table) -> String {
+    table.key.iter().map(|column| format!("{} = ?", column)).collect::<Vec<_>>().join(" AND ")
+}
+
+fn params(values: impl IntoIterator<Item = Option<String>>) -> Params {
+    Params::Positional(values.into_iter().map(Value::from).collect())
+}
+
+fn key_params(key: &Key) -> Params {
+    params(key.iter().cloned().map(Some))
+}
+
+fn insert(tx: &mut Transaction, table: &Table, rows: Vec<(Key, Vec<Option<String>>)>, report: &mut TableReport) -> AppResult<()> {
+    let columns: Vec<&str> = table.key.iter().chain(table.columns).copied().collect();
+    let query = format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        table.name,
+        columns.join(", "),
+        vec!["?"; columns.len()].join(", ")
+    );
+    report.inserted += rows.len();
+    tx.exec_batch(query, rows.into_iter().map(|(key, values)| params(key.into_iter().map(Some).chain(values))))
+        .map_err(|e| AppError::database(&format!("Failed to insert into {}", table.name), e))
+}
+
+// Writes the columns that differ, unless the conflict policy keeps a local edit
+fn update(
+    tx: &mut Transaction,
+    context: &Context,
+    table: &Table,
+    key: &Key,
+    local: &[Option<String>],
+    remote: &[Option<String>],
+    report: &mut TableReport,
+) -> AppResult<()> {
+    let row_key = row_key(key);
+    let mut changed = Vec::new();
+
+    for (i, column) in table.columns.iter().enumerate() {
+        if local[i] == remote[i] {
+            continue;
+        }
+        let Some(base_value) = context.edits.get(&(table.name.to_string(), row_key.clone(), column.to_string())) else {
+            changed.push(i);
+            continue;
+        };
+
+        let keep_local = match context.policy(table, column) {
+            ConflictPolicy::Upstream => false,
+            ConflictPolicy::Local => true,
+            ConflictPolicy::UpstreamIfChanged => remote[i] == *base_value,
+        };
+        if keep_local {
+            report.kept_local += 1;
+            continue;
+        }
+
+        println!("Sync replaced the local edit of {}.{} for {} with the upstream value", table.name, column, row_key);
+        tx.exec_drop(
+            "DELETE FROM local_edits WHERE table_name = ? AND row_key = ? AND column_name = ?",
+            (table.name, &row_key, column),
+        )
+        .map_err(|e| AppError::database("Failed to drop local edit", e))?;
+        changed.push(i);
+    }
+    if changed.is_empty() {
+        return Ok(());
+    }
+
+    let assignments: Vec<String> = changed.iter().map(|&i| format!("{} = ?", table.columns[i])).collect();
+    let query = format!("UPDATE {} SET {} WHERE {}", table.name, assignments.join(", "), where_key(table));
+    let values = changed.iter().map(|&i| remote[i].clone()).chain(key.iter().cloned().map(Some));
+    tx.exec_drop(query, params(values))
+        .map_err(|e| AppError::database(&format!("Failed to update {}", table.name), e))?;
+    report.updated += 1;
+    Ok(())
+}
+
+fn delete(tx: &mut Transaction, table: &Table, keys: Vec<Key>, report: &mut TableReport) -> AppResult<()> {
+    let query = format!("DELETE FROM {} WHERE {}", table.name, where_key(table));
+
+    'rows: for key in keys {
+        for (other, column) in table.referenced_by {
+            let used: Option<u8> = tx
+                .exec_first(format!("SELECT 1 FROM {} WHERE {} = ? LIMIT 1", other, column), key_params(&key))
+                .map_err(|e| AppError::database(&format!("Failed to check uses of {}", table.name), e))?;
+            if used.is_some() {
+                println!("Keeping {} {:?}: deleted upstream but used by {}", table.name, key, other);
+                report.skipped += 1;
+                continue 'rows;
+            }
+        }
+        tx.exec_drop(&query, key_params(&key))
+            .map_err(|e| AppError::database(&format!("Failed to delete from {}", table.name), e))?;
+        tx.exec_drop("DELETE FROM local_edits WHERE table_name = ? AND row_key = ?", (table.name, row_key(&key)))
+            .map_err(|e| AppError::database("Failed to drop local edits", e))?;
+        report.deleted += 1;
+    }
+    Ok(())
+}
+
+/// Brings `table`, or the rows of it within `scope`, in line with `remote`.
+/// Deletions can be left for later, for tables whose rows are still
+/// referenced by rows not yet deleted.
+fn mirror(
+    tx: &mut Transaction,
+    context: &Context,
+    table: &Table,
+    scope: Scope,
+    remote: Rows,
+    report: &mut TableReport,
+) -> AppResult<Vec<Key>> {
+    let local = load(tx, table, scope)?;
+
+    let deleted: Vec<Key> = local.keys().filter(|key| !remote.contains_key(*key)).cloned().collect();
+    // A scoped refresh only sees a few rows, so only whole tables are checked
+    if scope.is_none() {
+        check_deletions(context.config, table, local.len(), remote.len(), deleted.len())?;
+    }
+    let mut inserted = Vec::new();
+    for (key, values) in remote {
+        match local.get(&key) {
+            Some(local_values) => update(tx, context, table, &key, local_values, &values, report)?,
+            None => inserted.push((key, values)),
+        }
+    }
+    insert(tx, table, inserted, report)?;
+
+    Ok(deleted)
+}
+
+/// Refuses to mirror a table when upstream looks truncated: it returned
+/// nothing for a table that has rows, or would delete too many of them.
+fn check_deletions(config: &SyncConfig, table: &Table, local: usize, remote: usize, deleted: usize) -> AppResult<()> {
+    let details = serde_json::json!({ "table": table.name, "local": local, "remote": remote, "deleted": deleted });
+    if local > 0 && remote == 0 {
+        return Err(AppError::network(format!(
+            "Upstream returned no {} while {} exist locally; sync aborted",
+            table.name, local
+        ))
+        .with_details(details));
+    }
+    if deleted as f64 > local as f64 * config.max_delete_fraction {
+        return Err(AppError::network(format!(
+            "Sync would delete {} of {} {}, more than sync.max_delete_fraction allows; sync aborted",
+            deleted, local, table.name
+        ))
+        .with_details(details));
+    }
+    Ok(())
+}
+
+fn user_rows(users: Vec<User>) -> Rows {
+    users
+        .into_iter()
+        .map(|user| {
+            // A missing initial has always been stored as an empty string
+            let values = vec![Some(user.bn_number.into_inner()), Some(user.name), Some(user.major), Some(user.role), Some(user.initial.unwrap_or_default())];
+            (vec![user.nim], values)
+        })
+        .collect()
+}
+
+fn room_rows(rooms: Vec<Room>) -> Rows {
+    rooms
+        .into_iter()
+        .map(|room| (vec![room.room_number], vec![Some(room.room_capacity.to_string()), Some(room.campus)]))
+        .collect()
+}
+
+fn subject_rows(subjects: Vec<Subject>) -> Rows {
+    subjects
+        .into_iter()
+        .map(|subject| (vec![subject.subject_code], vec![Some(subject.subject_name)]))
+        .collect()
+}
+
+fn start(conn: &mut PooledConn) -> AppResult<Transaction<'_>> {
+    conn.start_transaction(TxOpts::default())
+        .map_err(|e| AppError::database("Failed to start transaction", e))
+}
+
+fn context<'a>(tx: &mut Transaction, config: &'a SyncConfig) -> AppResult<Context<'a>> {
+    let edits: Vec<(String, String, String, Option<String>)> = tx
+        .query("SELECT table_name, row_key, column_name, base_value FROM local_edits")
+        .map_err(|e| AppError::database("Failed to read local edits", e))?;
+    Ok(Context {
+        config,
+        edits: edits
+            .into_iter()
+            .map(|(table, key, column, base_value)| ((table, key, column), base_value))
+            .collect(),
+    })
+}
+
+fn apply(conn: &mut PooledConn, config: &SyncConfig, remote: Remote) -> AppResult<Vec<TableReport>> {
+    let users = user_rows(remote.users);
+    let rooms = room_rows(remote.rooms);
+    let subjects = subject_rows(remote.subjects);
+
+    // Enrollments of unknown users or subjects would break their foreign keys
+    let nims: BTreeSet<&String> = users.keys().map(|key| &key[0]).collect();
+    let subject_codes: BTreeSet<&String> = subjects.keys().map(|key| &key[0]).collect();
+    let mut enrollment_report = TableReport::new(&ENROLLMENTS);
+    let enrollments: Rows = remote
+        .enrollments
+        .into_iter()
+        .filter(|enrollment| {
+            let known = nims.contains(&enrollment.nim) && subject_codes.contains(&enrollment.subject_code);
+            if !known {
+                enrollment_report.skipped += 1;
+            }
+            known
+        })
+        .map(|enrollment| (vec![enrollment.subject_code, enrollment.class_code, enrollment.nim], Vec::new()))
+        .collect();
+
+    let mut tx = start(conn)?;
+    let context = context(&mut tx, config)?;
+
+    // Enrollments reference users and subjects, so their deletions go first and
+    // the parents' deletions last, once nothing points at them any more
+    let mut user_report = TableReport::new(&USERS);
+    let mut room_report = TableReport::new(&ROOMS);
+    let mut subject_report = TableReport::new(&SUBJECTS);
+    let deleted_users = mirror(&mut tx, &context, &USERS, None, users, &mut user_report)?;
+    let deleted_rooms = mirror(&mut tx, &context, &ROOMS, None, rooms, &mut room_report)?;
+    let deleted_subjects = mirror(&mut tx, &context, &SUBJECTS, None, subjects, &mut subject_report)?;
+    let deleted_enrollments = mirror(&mut tx, &context, &ENROLLMENTS, None, enrollments, &mut enrollment_report)?;
+    delete(&mut tx, &ENROLLMENTS, deleted_enrollments, &mut enrollment_report)?;
+    delete(&mut tx, &USERS, deleted_users, &mut user_report)?;
+    delete(&mut tx, &ROOMS, deleted_rooms, &mut room_report)?;
+    delete(&mut tx, &SUBJECTS, deleted_subjects, &mut subject_report)?;
+
+    tx.commit().map_err(|e| AppError::database("Failed to commit sync", e))?;
+    Ok(vec![user_report, room_report, subject_report, enrollment_report])
+}
+
+/// Fetches users, rooms, subjects and enrollments and applies the differences
+/// to MySQL in one transaction. Every run is recorded in `sync_runs` with
+/// `triggered_by`. Callers hold the `SyncLock`; a sync already running on
+/// another machine fails with `SyncInProgress`.
+pub async fn run(
+    pool: &Pool,
+    graphql: &GraphqlConfig,
+    config: &SyncConfig,
+    triggered_by: &str,
+    progress: ProgressFn<'_>,
+) -> AppResult<SyncReport> {
+    let mut conn = pool.get_conn().map_err(|e| AppError::database("Failed to get connection", e))?;
+
+    // Named locks belong to the connection, so the whole run uses this one
+    let acquired: Option<Option<i64>> = conn
+        .exec_first("SELECT GET_LOCK(?, 0)", (LOCK_NAME,))
+        .map_err(|e| AppError::database("Failed to acquire sync lock", e))?;
+    if acquired.flatten() != Some(1) {
+        return Err(AppError::new(ErrorCode::SyncInProgress, "A sync is already running on another machine"));
+    }
+
+    let result = run_locked(&mut conn, graphql, config, triggered_by, progress).await;
+
+    if let Err(e) = conn.exec_drop("DO RELEASE_LOCK(?)", (LOCK_NAME,)) {
+        println!("Failed to release sync lock: {}", e);
+    }
+
+    result
+}
+
+async fn run_locked(
+    conn: &mut PooledConn,
+    graphql: &GraphqlConfig,
+    config: &SyncConfig,
+    triggered_by: &str,
+    progress: ProgressFn<'_>,
+) -> AppResult<SyncReport> {
+    conn.exec_drop("INSERT INTO sync_runs (status, triggered_by) VALUES ('running', ?)", (triggered_by,))
+        .map_err(|e| AppError::database("Failed to record sync run", e))?;
+    let run_id = conn.last_insert_id();
+    let report = |stage, message| progress(Progress { run_id, stage, message });
+    report("started", format!("Sync started by {}", triggered_by));
+
+    let result = match fetch(graphql, report).await {
+        Ok(remote) => {
+            report("applying", "Applying changes".to_string());
+            apply(conn, config, remote)
+        }
+        Err(e) => Err(e),
+    };
+    if let Ok(tables) = &result {
+        for table in tables {
+            report(
+                "applied",
+                format!(
+                    "{}: {} inserted, {} updated, {} deleted, {} skipped",
+                    table.table, table.inserted, table.updated, table.deleted, table.skipped
+                ),
+            );
+        }
+    }
+
+    let finished = match &result {
+        Ok(tables) => {
+            let report = SyncReport { run_id, tables: tables.clone() };
+            let (inserted, updated, deleted) = report.totals();
+            println!("Sync run {}: {}", run_id, report.summary());
+            conn.exec_drop(
+                r"UPDATE sync_runs SET finished_at = NOW(), status = 'succeeded',
+                    inserted = ?, updated = ?, deleted = ?, report = ?
+                WHERE id = ?",
+                (inserted, updated, deleted, serde_json::to_string(&report.tables).unwrap_or_default(), run_id),
+            )
+        }
+        Err(e) => {
+            println!("Sync run {} failed: {}", run_id, e);
+            conn.exec_drop(
+                "UPDATE sync_runs SET finished_at = NOW(), status = 'failed', error = ? WHERE id = ?",
+                (&e.message, run_id),
+            )
+        }
+    };
+    finished.map_err(|e| AppError::database("Failed to record sync run", e))?;
+
+    result.map(|tables| SyncReport { run_id, tables })
+}
+
+// Inserts and updates `remote` rows of a table keyed by one column, leaving
+// every other row alone
+fn upsert(conn: &mut PooledConn, config: &SyncConfig, table: &Table, remote: Rows) -> AppResult<TableReport> {
+    let mut report = TableReport::new(table);
+    let mut tx = start(conn)?;
+    let context = context(&mut tx, config)?;
+
+    let mut inserted = Vec::new();
+    for (key, values) in remote {
+        let local = load(&mut tx, table, Some((table.key[0], &key[0])))?;
+        match local.get(&key) {
+            Some(local_values) => update(&mut tx, &context, table, &key, local_values, &values, &mut report)?,
+            None => inserted.push((key, values)),
+        }
+    }
+    insert(&mut tx, table, inserted, &mut report)?;
+
+    tx.commit().map_err(|e| AppError::database(&format!("Failed to commit refresh of {}", table.name), e))?;
+    Ok(report)
+}
+
+/// Applies users fetched by a targeted lookup, with the same conflict
+/// policies as a full sync. Users missing from `users` are not deleted.
+pub fn refresh_users(conn: &mut PooledConn, config: &SyncConfig, users: Vec<User>) -> AppResult<TableReport> {
+    upsert(conn, config, &USERS, user_rows(users))
+}
+
+pub fn refresh_rooms(conn: &mut PooledConn, config: &SyncConfig, rooms: Vec<Room>) -> AppResult<TableReport> {
+    upsert(conn, config, &ROOMS, room_rows(rooms))
+}
+
+pub fn refresh_subjects(conn: &mut PooledConn, config: &SyncConfig, subjects: Vec<Subject>) -> AppResult<TableReport> {
+    upsert(conn, config, &SUBJECTS, subject_rows(subjects))
+}
+
+/// Replaces the enrollments of one student with `enrollments`. Those for
+/// subjects not synced yet are skipped, and nothing is stored for a student
+/// who has not been synced.
+pub fn refresh_enrollments(conn: &mut PooledConn, config: &SyncConfig, nim: &str, enrollments: Vec<Enrollment>) -> AppResult<TableReport> {
+    let mut report = TableReport::new(&ENROLLMENTS);
+    let mut tx = start(conn)?;
+
+    let known: Option<u8> = tx
+        .exec_first("SELECT 1 FROM users WHERE nim = ?", (nim,))
+        .map_err(|e| AppError::database("Failed to query users", e))?;
+    if known.is_none() {
+        report.skipped = enrollments.len();
+        return Ok(report);
+    }
+
+    let subject_codes: BTreeSet<String> = tx
+        .query("SELECT subject_code FROM subjects")
+        .map_err(|e| AppError::database("Failed to query subjects", e))?
+        .into_iter()
+        .collect();
+    let rows: Rows = enrollments
+        .into_iter()
+        .filter(|enrollment| {
+            let known = enrollment.nim == nim && subject_codes.contains(&enrollment.subject_code);
+            if !known {
+                report.skipped += 1;
+            }
+            known
+        })
+        .map(|enrollment| (vec![enrollment.subject_code, enrollment.class_code, enrollment.nim], Vec::new()))
+        .collect();
+
+    let context = context(&mut tx, config)?;
+    let deleted = mirror(&mut tx, &context, &ENROLLMENTS, Some(("nim", nim)), rows, &mut report)?;
+    delete(&mut tx, &ENROLLMENTS, deleted, &mut report)?;
+
+    tx.commit().map_err(|e| AppError::database("Failed to commit refresh of enrollments", e))?;
+    Ok(report)
+}
+
+/// When the last successful sync finished and how many seconds ago, both
+/// `None` before the first one.
+pub fn last_synced(conn: &mut PooledConn) -> AppResult<(Option<u64>, Option<u64>)> {
+    let row: Option<(Option<u64>, u64)> = conn
+        .query_first("SELECT UNIX_TIMESTAMP(MAX(finished_at)), UNIX_TIMESTAMP() FROM sync_runs WHERE status = 'succeeded'")
+        .map_err(|e| AppError::database("Failed to query sync runs", e))?;
+
+    let (synced_at, now) = row.unwrap_or_default();
+    Ok((synced_at, synced_at.map(|synced_at| now.saturating_sub(synced_at))))
+}
+
+// id, started_at, finished_at, status, triggered_by, report and error
+type SyncRunRow = (u64, u64, Option<u64>, String, String, Option<String>, Option<String>);
+
+pub fn runs(conn: &mut PooledConn, limit: u32) -> AppResult<Vec<SyncRun>> {
+    conn.exec_map(
+        r"SELECT id, UNIX_TIMESTAMP(started_at), UNIX_TIMESTAMP(finished_at), status, triggered_by, report, error
+        FROM sync_runs
+        ORDER BY id DESC
+        LIMIT ?",
+        (limit,),
+        |(id, started_at, finished_at, status, triggered_by, report, error): SyncRunRow| SyncRun {
+            id,
+            started_at,
+            finished_at,
+            status,
+            triggered_by,
+            report: report
+                .and_then(|report| serde_json::from_str(&report).ok())
+                .unwrap_or(serde_json::Value::Null),
+            error,
+        },
+    )
+    .map_err(|e| AppError::database("Failed to query sync runs", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aborts_when_upstream_looks_truncated() {
+        let config = SyncConfig::default();
+        for ((local, remote, deleted), allowed) in [
+            ((0, 0, 0), true),
+            ((10, 10, 0), true),
+            ((10, 5, 5), true),
+            ((10, 4, 6), false),
+            ((10, 0, 10), false),
+            // Upstream returning nothing is refused even when deleting everything is allowed
+            ((1, 0, 1), false),
+        ] {
+            let result = check_deletions(&config, &USERS, local, remote, deleted);
+            assert_eq!(result.is_ok(), allowed, "{} local, {} remote, {} deleted", local, remote, deleted);
+        }
+
+        let anything_goes = SyncConfig { max_delete_fraction: 1.0, ..SyncConfig::default() };
+        assert!(check_deletions(&anything_goes, &USERS, 10, 1, 9).is_ok());
+        assert!(check_deletions(&anything_goes, &USERS, 10, 0, 10).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha1::Sha1;
+
+use crate::clock;
+
+/// RFC 6238 defaults, which every authenticator app understands.
+const STEP_SECS: u64 = 30;
+const DIGITS: u32 = 6;
+const SECRET_BYTES: usize = 20;
+/// Steps either side of now that are still accepted, for clock drift.
+const ALLOWED_DRIFT: u64 = 1;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; SECRET_BYTES];
+    OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+/// RFC 4648 base32 without padding, the form authenticator apps expect.
+pub fn base32(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// The `otpauth://` URI to show as a QR code when enrolling.
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &[u8]) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECS}",
+        issuer = percent_encode(issuer),
+        account = percent_encode(account),
+        secret = base32(secret),
+    )
+}
+
+fn current_step() -> u64 {
+    clock::now() / STEP_SECS
+}
+
+// HOTP (RFC 4226) value of one time step
+fn code_at(secret: &[u8], step: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+    value % 10u32.pow(DIGITS)
+}
+
+/// The time step `code` is valid for, if it matches one within the allowed
+/// drift and is newer than `last_step`, so a code cannot be used twice.
+pub fn verify(secret: &[u8], code: &str, last_step: Option<u64>) -> Option<u64> {
+    verify_at(secret, code, last_step, current_step())
+}
+
+fn verify_at(secret: &[u8], code: &str, last_step: Option<u64>, now: u64) -> Option<u64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+
+    (now.saturating_sub(ALLOWED_DRIFT)..=now + ALLOWED_DRIFT)
+        .filter(|step| last_step.is_none_or(|last| *step > last))
+        .find(|step| code_at(secret, *step) == code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The shared secret of the RFC 4226 and RFC 6238 SHA-1 test vectors
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    fn code_string(step: u64) -> String {
+        format!("{:06}", code_at(RFC_SECRET, step))
+    }
+
+    #[test]
+    fn matches_rfc_6238_sha1_vectors() {
+        // Appendix B lists 8 digits; a 6 digit code is their last 6
+        for (time, expected) in [
+            (59u64, 94287082u32),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+            (20000000000, 65353130),
+        ] {
+            assert_eq!(code_at(RFC_SECRET, time / STEP_SECS), expected % 1_000_000, "T = {}", time);
+        }
+    }
+
+    #[test]
+    fn matches_rfc_4226_hotp_vectors() {
+        let expected = [755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489];
+        for (counter, value) in expected.iter().enumerate() {
+            assert_eq!(code_at(RFC_SECRET, counter as u64), *value, "counter {}", counter);
+        }
+    }
+
+    #[test]
+    fn matches_rfc_4648_base32_vectors() {
+        // Section 10, without the padding authenticator apps do not expect
+        for (input, expected) in [
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ] {
+            assert_eq!(base32(input.as_bytes()), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn accepts_one_step_of_drift_either_way() {
+        let now = 1_000_000;
+        for step in [now - 1, now, now + 1] {
+            assert_eq!(verify_at(RFC_SECRET, &code_string(step), None, now), Some(step), "step {}", step);
+        }
+        for step in [now - 2, now + 2] {
+            assert_eq!(verify_at(RFC_SECRET, &code_string(step), None, now), None, "step {}", step);
+        }
+    }
+
+    #[test]
+    fn rejects_a_code_already_used() {
+        let now = 1_000_000;
+        assert_eq!(verify_at(RFC_SECRET, &code_string(now), Some(now), now), None);
+        assert_eq!(verify_at(RFC_SECRET, &code_string(now - 1), Some(now - 1), now), None);
+        assert_eq!(verify_at(RFC_SECRET, &code_string(now + 1), Some(now), now), Some(now + 1));
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        let now = 1_000_000;
+        let code = code_string(now);
+        assert_eq!(verify_at(RFC_SECRET, &format!(" {} ", code), None, now), Some(now));
+        for malformed in ["", "12345", "1234567", "12345a", "12 345"] {
+            assert_eq!(verify_at(RFC_SECRET, malformed, None, now), None, "{:?}", malformed);
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use mysql::prelude::*;
+use mysql::{PooledConn, TxOpts};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::sessions::new_token;
+use crate::totp;
+use crate::CurrentUser;
+
+pub const ISSUER: &str = "SR Exam";
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Time allowed between the password and the second login step.
+const CHALLENGE_LIFETIME: Duration = Duration::from_secs(5 * 60);
+
+/// What the user scans or types into an authenticator app.
+#[derive(Clone, Debug, Serialize)]
+pub struct Enrollment {
+    /// Base32 secret for manual entry.
+    pub secret: String,
+    /// `otpauth://` URI, also the QR code payload.
+    pub otpauth_uri: String,
+}
+
+struct Settings {
+    secret: Option<Vec<u8>>,
+    enabled: bool,
+    last_step: Option<u64>,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    (0..text.len())
+        .step_by(2)
+        .map(|i| text.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect()
+}
+
+/// A random single-use code formatted as `XXXX-XXXX`, for people to type.
+pub fn generate_code() -> String {
+    let mut bytes = [0u8; 5];
+    OsRng.fill_bytes(&mut bytes);
+    let code = totp::base32(&bytes);
+    format!("{}-{}", &code[..4], &code[4..])
+}
+
+/// How single-use codes are stored. Case and separators do not matter.
+pub fn hash_code(code: &str) -> String {
+    let normalized: String = code.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_uppercase();
+    to_hex(&Sha256::digest(normalized.as_bytes()))
+}
+
+fn settings(conn: &mut PooledConn, bn_number: &str) -> AppResult<Settings> {
+    let row: Option<(Option<String>, bool, Option<u64>)> = conn
+        .exec_first(
+            "SELECT totp_secret, totp_enabled, totp_last_step FROM users WHERE bn_number = ?",
+            (bn_number,),
+        )
+        .map_err(|e| AppError::database("Failed to query two-factor settings", e))?;
+
+    let (secret, enabled, last_step) = row.ok_or_else(|| AppError::not_found(format!("User {} not found", bn_number)))?;
+    Ok(Settings {
+        secret: secret.as_deref().and_then(from_hex),
+        enabled,
+        last_step,
+    })
+}
+
+pub fn is_enabled(conn: &mut PooledConn, bn_number: &str) -> AppResult<bool> {
+    Ok(settings(conn, bn_number)?.enabled)
+}
+
+pub fn required_for(conn: &mut PooledConn, role: &str) -> AppResult<bool> {
+    let required: Option<String> = conn
+        .exec_first("SELECT role FROM two_factor_roles WHERE role = ?", (role,))
+        .map_err(|e| AppError::database("Failed to query two-factor roles", e))?;
+    Ok(required.is_some())
+}
+
+pub fn required_roles(conn: &mut PooledConn) -> AppResult<Vec<String>> {
+    conn.query("SELECT role FROM two_factor_roles ORDER BY role")
+        .map_err(|e| AppError::database("Failed to query two-factor roles", e))
+}
+
+/// Replaces the set of roles that must use two-factor authentication.
+pub fn set_required_roles(conn: &mut PooledConn, roles: &[String]) -> AppResult<()> {
+    let mut transaction = conn
+        .start_transaction(TxOpts::default())
+        .map_err(|e| AppError::database("Failed to start transaction", e))?;
+
+    transaction
+        .query_drop("DELETE FROM two_factor_roles")
+        .map_err(|e| AppError::database("Failed to clear two-factor roles", e))?;
+    transaction
+        .exec_batch("INSERT IGNORE INTO two_factor_roles (role) VALUES (?)", roles.iter().map(|role| (role,)))
+        .map_err(|e| AppError::database("Failed to save two-factor roles", e))?;
+
+    transaction.commit().map_err(|e| AppError::database("Failed to commit transaction", e))
+}
+
+/// Stores a new secret for `user`. It only takes effect once
+/// `confirm_enrollment` has seen a valid code from it.
+pub fn begin_enrollment(conn: &mut PooledConn, user: &CurrentUser) -> AppResult<Enrollment> {
+    if is_enabled(conn, &user.bn_number)? {
+        return Err(AppError::validation("Two-factor authentication is already enabled"));
+    }
+
+    let secret = totp::generate_secret();
+    conn.exec_drop(
+        "UPDATE users SET totp_secret = ?, totp_enabled = FALSE, totp_last_step = NULL WHERE bn_number = ?",
+        (to_hex(&secret), &user.bn_number),
+    )
+    .map_err(|e| AppError::database("Failed to store two-factor secret", e))?;
+
+    let account = user.initial.clone().filter(|initial| !initial.is_empty()).unwrap_or_else(|| user.nim.clone());
+    Ok(Enrollment {
+        secret: totp::base32(&secret),
+        otpauth_uri: totp::otpauth_uri(ISSUER, &account, &secret),
+    })
+}
+
+/// Turns two-factor on once `code` proves the authenticator is set up, and
+/// returns a fresh set of recovery codes. They are only ever shown here.
+pub fn confirm_enrollment(conn: &mut PooledConn, bn_number: &str, code: &str) -> AppResult<Vec<String>> {
+    let settings = settings(conn, bn_number)?;
+    let secret = settings
+        .secret
+        .ok_or_else(|| AppError::validation("Start two-factor enrollment before confirming it"))?;
+    let step = totp::verify(&secret, code, None)
+        .ok_or_else(|| AppError::new(ErrorCode::InvalidCredentials, "Invalid two-factor code"))?;
+
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| generate_code()).collect();
+
+    let mut transaction = conn
+        .start_transaction(TxOpts::default())
+        .map_err(|e| AppError::database("Failed to start transaction", e))?;
+    transaction
+        .exec_drop(
+            "UPDATE users SET totp_enabled = TRUE, totp_last_step = ? WHERE bn_number = ?",
+            (step, bn_number),
+        )
+        .map_err(|e| AppError::database("Failed to enable two-factor", e))?;
+    transaction
+        .exec_drop("DELETE FROM recovery_codes WHERE bn_number = ?", (bn_number,))
+        .map_err(|e| AppError::database("Failed to replace recovery codes", e))?;
+    transaction
+        .exec_batch(
+            "INSERT INTO recovery_codes (bn_number, code_hash) VALUES (?, ?)",
+            codes.iter().map(|code| (bn_number, hash_code(code))),
+        )
+        .map_err(|e| AppError::database("Failed to store recovery codes", e))?;
+    transaction.commit().map_err(|e| AppError::database("Failed to commit transaction", e))?;
+
+    Ok(codes)
+}
+
+/// Checks an authenticator code, or else spends a recovery code. Each code
+/// works once.
+pub fn verify_code(conn: &mut PooledConn, bn_number: &str, code: &str) -> AppResult<bool> {
+    let settings = settings(conn, bn_number)?;
+    let Some(secret) = settings.secret.filter(|_| settings.enabled) else {
+        return Ok(false);
+    };
+
+    if let Some(step) = totp::verify(&secret, code, settings.last_step) {
+        conn.exec_drop("UPDATE users SET totp_last_step = ? WHERE bn_number = ?", (step, bn_number))
+            .map_err(|e| AppError::database("Failed to record two-factor code", e))?;
+        return Ok(true);
+    }
+
+    conn.exec_drop(
+        "UPDATE recovery_codes SET used_at = NOW() WHERE bn_number = ? AND code_hash = ? AND used_at IS NULL LIMIT 1",
+        (bn_number, hash_code(code)),
+    )
+    .map_err(|e| AppError::database("Failed to check recovery code", e))?;
+    if conn.affected_rows() > 0 {
+        println!("{} logged in with a recovery code", bn_number);
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+pub fn disable(conn: &mut PooledConn, bn_number: &str) -> AppResult<()> {
+    conn.exec_drop(
+        "UPDATE users SET totp_secret = NULL, totp_enabled = FALSE, totp_last_step = NULL WHERE bn_number = ?",
+        (bn_number,),
+    )
+    .map_err(|e| AppError::database("Failed to disable two-factor", e))?;
+    conn.exec_drop("DELETE FROM recovery_codes WHERE bn_number = ?", (bn_number,))
+        .map_err(|e| AppError::database("Failed to delete recovery codes", e))
+}
+
+/// A login whose password was right and which waits for its second step.
+#[derive(Clone)]
+pub struct PendingLogin {
+    /// What the user typed, for login throttling.
+    pub username: String,
+    pub user: CurrentUser,
+    pub remember_me: bool,
+    started_at: Instant,
+}
+
+#[derive(Default)]
+pub struct PendingLogins {
+    pending: Mutex<HashMap<String, PendingLogin>>,
+}
+
+impl PendingLogins {
+    /// Parks the login and returns the challenge token for `verify_two_factor`.
+    pub fn create(&self, username: &str, user: CurrentUser, remember_me: bool) -> AppResult<String> {
+        let challenge = new_token();
+        let mut pending = self.pending.lock()?;
+        pending.retain(|_, login| login.started_at.elapsed() < CHALLENGE_LIFETIME);
+        pending.insert(
+            challenge.clone(),
+            PendingLogin {
+                username: username.to_string(),
+                user,
+                remember_me,
+                started_at: Instant::now(),
+            },
+        );
+        Ok(challenge)
+    }
+
+    pub fn get(&self, challenge: &str) -> AppResult<PendingLogin> {
+        match self.pending.lock()?.get(challenge) {
+            Some(login) if login.started_at.elapsed() < CHALLENGE_LIFETIME => Ok(login.clone()),
+            _ => Err(AppError::new(ErrorCode::SessionExpired, "The login expired, please sign in again")),
+        }
+    }
+
+    pub fn remove(&self, challenge: &str) -> AppResult<()> {
+        self.pending.lock()?.remove(challenge);
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
-import { invoke } from "@tauri-apps/api";
+import { clearSessionToken, invokeWithSession, listenForSessionExpiry } from "../session";
+import { listenForSync } from "../sync";
 import { useEffect, useState } from "react";
 import { Link as RouterLink, useNavigate } from "react-router-dom";
 
@@ -6,10 +7,11 @@ export default function NavbarComponent() {
   const [user, setUser] = useState<User | null>(null);
   const [greeting, setGreeting] = useState("Guest");
   const [homeRoute, setHomeRoute] = useState("/");
+  const [syncStatus, setSyncStatus] = useState("");
   const navigate = useNavigate();
 
   useEffect(() => {
-    invoke("get_current_user").then((user) => {
+    invokeWithSession("get_current_user").then((user) => {
       console.log(user);
       setUser(user as User);
     }).catch((error) => {
@@ -17,6 +19,41 @@ export default function NavbarComponent() {
     });
   }, []);
 
+  useEffect(() => {
+    const unlisten = listenForSessionExpiry(() => {
+      setUser(null);
+      navigate("/", { replace: true });
+    });
+    return () => {
+      unlisten.then((stop) => stop());
+    };
+  }, []);
+
+  useEffect(() => {
+    const unlisten = listenForSync(
+      (progress) => setSyncStatus(progress.message),
+      (done) => {
+        if (done.error) {
+          setSyncStatus(`Sync failed: ${done.error.message}`);
+        } else if (done.report) {
+          const changes = done.report.tables.reduce((total, table) => total + table.inserted + table.updated + table.deleted, 0);
+          setSyncStatus(`Synced at ${new Date().toLocaleTimeString()} (${changes} changes)`);
+        }
+      }
+    );
+    return () => {
+      unlisten.then((stop) => stop());
+    };
+  }, []);
+
+  const handleSyncNow = async () => {
+    try {
+      await invokeWithSession("trigger_sync");
+    } catch (error: any) {
+      setSyncStatus(error?.message ?? "Failed to start a sync.");
+    }
+  };
+
   useEffect(() => {
     if (user) {
       setGreeting(`Hello, ${user.nim ? user.nim + ' - ' + user.name : user.initial}`);
@@ -29,11 +66,23 @@ export default function NavbarComponent() {
   };
 
   const handleLogout = async () => {
-    await invoke("logout");
+    // An expired session is already gone, so leave either way
+    await invokeWithSession("logout").catch((error) => console.error("Failed to log out:", error));
+    clearSessionToken();
     setUser(null);
     navigate("/");
   };
 
+  const handleStopImpersonation = async () => {
+    try {
+      const admin = await invokeWithSession<User>("stop_impersonation");
+      setUser(admin);
+      navigate("/user-management");
+    } catch (error) {
+      console.error("Failed to stop viewing as user:", error);
+    }
+  };
+
   const renderProfileName = (user: User | null) => {
     if (!user) {
       return "Guest";
@@ -92,6 +141,17 @@ export default function NavbarComponent() {
   };
 
   return (
+    <>
+    {user?.impersonated_by && (
+      <div className="alert alert-warning d-flex justify-content-between align-items-center mb-0 rounded-0">
+        <span>
+          Viewing as {user.name} ({user.bn_number}). Changes are disabled.
+        </span>
+        <button className="btn btn-sm btn-dark" onClick={handleStopImpersonation}>
+          Stop viewing
+        </button>
+      </div>
+    )}
     <nav className="navbar navbar-expand-lg navbar-dark bg-dark">
       <div className="container-fluid">
         <RouterLink to={homeRoute} className="navbar-brand">
@@ -106,6 +166,14 @@ export default function NavbarComponent() {
             {user ? renderNavLinks(user.role) : null}
           </div>
         </div>
+        {user?.role === "Exam Coordinator" && (
+          <div className="d-flex align-items-center ms-auto me-3">
+            {syncStatus && <small className="text-light me-2">{syncStatus}</small>}
+            <button className="btn btn-sm btn-outline-light" onClick={handleSyncNow}>
+              Sync now
+            </button>
+          </div>
+        )}
         <div className="dropdown ms-auto">
           <button
             className="btn btn-secondary dropdown-toggle"
@@ -144,5 +212,6 @@ export default function NavbarComponent() {
         </div>
       </div>
     </nav>
+    </>
   );
 }
\ No newline at end of file
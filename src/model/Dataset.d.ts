@@ -0,0 +1,6 @@
+interface Dataset<T> {
+    rows: T[];
+    source: "local" | "remote";
+    synced_at: number | null;
+    age_secs: number | null;
+}
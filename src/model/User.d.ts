@@ -6,4 +6,7 @@ interface User {
     nim: String,
     role: String,
     login_type: "nim" | "initial";
+    must_change_password: boolean;
+    must_enroll_two_factor: boolean;
+    impersonated_by: string | null;
 }
\ No newline at end of file
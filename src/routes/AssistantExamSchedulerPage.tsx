@@ -1,6 +1,6 @@
 import NavbarComponent from "../components/NavbarComponent";
 import { useEffect, useState } from "react";
-import { invoke } from "@tauri-apps/api";
+import { invokeWithSession } from "../session";
 
 export default function AssistantExamSchedulerPage() {
   const [transactions, setTransactions] = useState<ViewTransaction[]>([]);
@@ -15,13 +15,12 @@ export default function AssistantExamSchedulerPage() {
   const [selectionPairs, setSelectionPairs] = useState<{ transaction: ViewTransaction, assistant: User }[]>([]);
 
   useEffect(() => {
-    invoke("view_transaction").then((result) => {
+    invokeWithSession("view_transaction").then((result) => {
       setTransactions(result as ViewTransaction[]);
     });
 
-    invoke("get_all_users").then((result) => {
-      const users = result as User[];
-      const assistants = users.filter(user => user.role === "Assistant");
+    invokeWithSession<Dataset<User>>("get_all_users").then((result) => {
+      const assistants = result.rows.filter(user => user.role === "Assistant");
       setAssistants(assistants);
     });
   }, []);
@@ -68,7 +67,7 @@ export default function AssistantExamSchedulerPage() {
     // Iterate over each pair and invoke the Rust command to update the proctor
     selectionPairs.forEach(async (pair) => {
       try {
-        await invoke("update_transaction_proctor", {
+        await invokeWithSession("update_transaction_proctor", {
           transaction_code: pair.transaction.transaction_code,
           selected_assistant: pair.assistant.nim // or pair.assistant.nim, depending on what uniquely identifies the assistant
         });
@@ -1,49 +1,112 @@
 import "bootstrap/dist/css/bootstrap.min.css";
 import { useEffect, useState } from "react";
 import { invoke } from "@tauri-apps/api";
+import { invokeWithSession, saveSessionToken } from "../session";
 import { useNavigate } from "react-router-dom";
 import "../css/LoginPage.css";
 
-export default function LoginPage() {
-  
-  const[subjects, setSubject] = useState<Subject[]>([]);
+type LoginResponse = {
+  token: string | null;
+  login_type: string;
+  must_change_password: boolean;
+  must_enroll_two_factor: boolean;
+  two_factor_challenge: string | null;
+};
 
-  useEffect(() => {
-    invoke("get_all_subject", {}).then((rooms) => {
-      console.log(rooms);
-      setSubject(rooms as Subject[]); 
-    });
-  }, []);  // <-- Add this empty array  
-  
+export default function LoginPage() {
   const navigate = useNavigate();
   const [formData, setFormData] = useState({
     username: "",
     password: "",
+    rememberMe: false,
   });
   const [error, setError] = useState("");
+  const [twoFactorChallenge, setTwoFactorChallenge] = useState<string | null>(null);
+  const [twoFactorCode, setTwoFactorCode] = useState("");
+  const [resetting, setResetting] = useState(false);
+  const [resetForm, setResetForm] = useState({ code: "", newPassword: "" });
+  const [notice, setNotice] = useState("");
 
   const handleInputChange = (event) => {
-    const { name, value } = event.target;
+    const { name, value, type, checked } = event.target;
     setFormData((prevFormData) => ({
       ...prevFormData,
-      [name]: value,
+      [name]: type === "checkbox" ? checked : value,
     }));
   };
 
+  const enterSession = ({ token, login_type: userType, must_change_password, must_enroll_two_factor, two_factor_challenge }: LoginResponse) => {
+    if (!token) {
+      setTwoFactorChallenge(two_factor_challenge);
+      setTwoFactorCode("");
+      return;
+    }
+    saveSessionToken(token);
+    if (must_change_password || must_enroll_two_factor) {
+      navigate("/profile-page", { replace: true });
+    } else if (userType === "nim") {
+      // Picks up enrollment changes made upstream since the last sync
+      invokeWithSession<User | null>("get_current_user")
+        .then((user) => user && invokeWithSession("refresh_enrollment_by_nim", { nim: user.nim }))
+        .catch((e) => console.error(e));
+      navigate("/home", { replace: true });
+    } else if (userType === "initial") {
+      navigate("/dashboard", { replace: true });
+    } else {
+      setError("Invalid username or password.");
+    }
+  };
+
+  useEffect(() => {
+    invoke<LoginResponse | null>("restore_session").then((response) => {
+      if (response) {
+        enterSession(response);
+      }
+    }).catch((error) => {
+      console.error("Failed to restore session:", error);
+    });
+  }, []);
+
   const login = () => {
-    invoke("login", formData).then((userType) => {
-      if (userType === "nim") {
-        navigate("/home", { replace: true });
-      } else if (userType === "initial") {
-        navigate("/dashboard", { replace: true });
-      } else {
+    invoke<LoginResponse>("login", formData).then(enterSession).catch((error) => {
+      if (error?.code === "INVALID_CREDENTIALS") {
         setError("Invalid username or password.");
+      } else if (error?.code === "ACCOUNT_SUSPENDED") {
+        const until = error.details?.suspended_until;
+        setError(
+          `Your account has been suspended${until ? ` until ${new Date(until * 1000).toLocaleString()}` : ""}` +
+            `${error.details?.reason ? `: ${error.details.reason}` : "."}`
+        );
+      } else {
+        setError(error?.message ?? "An error occurred during login.");
       }
-    }).catch(() => {
-      setError("An error occurred during login.");
     });
   };
 
+  const resetPassword = () => {
+    invoke("reset_password", { username: formData.username, code: resetForm.code, newPassword: resetForm.newPassword })
+      .then(() => {
+        setResetting(false);
+        setResetForm({ code: "", newPassword: "" });
+        setError("");
+        setNotice("Password changed. Log in with your new password.");
+      })
+      .catch((error) => {
+        setError(error?.message ?? "An error occurred while resetting the password.");
+      });
+  };
+
+  const verifyTwoFactor = () => {
+    invoke<LoginResponse>("verify_two_factor", { challenge: twoFactorChallenge, code: twoFactorCode })
+      .then(enterSession)
+      .catch((error) => {
+        if (error?.code === "SESSION_EXPIRED" || error?.code === "ACCOUNT_LOCKED") {
+          setTwoFactorChallenge(null);
+        }
+        setError(error?.message ?? "An error occurred during login.");
+      });
+  };
+
   return (
     <div className="login-container">
       <img src="src/assets/logo-white.png" alt="Logo" className="logo" />
@@ -54,6 +117,91 @@ export default function LoginPage() {
               {error}
             </div>
           )}
+          {notice && (
+            <div className="alert alert-success" role="alert">
+              {notice}
+            </div>
+          )}
+          {resetting ? (
+          <>
+          <div className="mb-3">
+            <label htmlFor="resetUsername" className="form-label">
+              Username
+            </label>
+            <input
+              type="text"
+              className="form-control custom-input"
+              id="resetUsername"
+              name="username"
+              value={formData.username}
+              onChange={handleInputChange}
+              placeholder="Username"
+            />
+          </div>
+          <div className="mb-3">
+            <label htmlFor="resetCode" className="form-label">
+              Reset code
+            </label>
+            <input
+              type="text"
+              className="form-control custom-input"
+              id="resetCode"
+              value={resetForm.code}
+              onChange={(e) => setResetForm({ ...resetForm, code: e.target.value })}
+              placeholder="XXXX-XXXX"
+            />
+          </div>
+          <div className="mb-3">
+            <label htmlFor="resetNewPassword" className="form-label">
+              New password
+            </label>
+            <input
+              type="password"
+              className="form-control custom-input"
+              id="resetNewPassword"
+              value={resetForm.newPassword}
+              onChange={(e) => setResetForm({ ...resetForm, newPassword: e.target.value })}
+              placeholder="New password"
+            />
+          </div>
+          <button
+            type="button"
+            onClick={resetPassword}
+            className="bg-slate-500 hover:bg-slate-700 text-white font-bold py-2 px-4 rounded focus:outline-none focus:shadow-outline"
+          >
+            Set Password
+          </button>
+          <button type="button" className="btn btn-link" onClick={() => setResetting(false)}>
+            Back to login
+          </button>
+          </>
+          ) : twoFactorChallenge ? (
+          <>
+          <div className="mb-3">
+            <label htmlFor="twoFactorCode" className="form-label">
+              Authenticator or recovery code
+            </label>
+            <input
+              type="text"
+              className="form-control custom-input"
+              id="twoFactorCode"
+              name="twoFactorCode"
+              autoComplete="one-time-code"
+              value={twoFactorCode}
+              onChange={(e) => setTwoFactorCode(e.target.value)}
+              placeholder="123456"
+            />
+          </div>
+          <button
+            type="button"
+            onClick={verifyTwoFactor}
+            className="bg-slate-500 hover:bg-slate-700 text-white font-bold py-2 px-4 rounded focus:outline-none focus:shadow-outline"
+          >
+            Verify
+          </button>
+          </>
+          ) : (
+          <>
           <div className="mb-3">
             <label htmlFor="exampleInputEmail1" className="form-label">
               Username
@@ -80,6 +228,19 @@ export default function LoginPage() {
               placeholder="Password"
             />
           </div>
+          <div className="mb-3 form-check">
+            <input
+              type="checkbox"
+              className="form-check-input"
+              id="rememberMe"
+              name="rememberMe"
+              checked={formData.rememberMe}
+              onChange={handleInputChange}
+            />
+            <label htmlFor="rememberMe" className="form-check-label">
+              Remember me
+            </label>
+          </div>
           <button
             type="button"
             onClick={login}
@@ -87,6 +248,11 @@ export default function LoginPage() {
           >
             Login
           </button>
+          <button type="button" className="btn btn-link" onClick={() => { setResetting(true); setError(""); setNotice(""); }}>
+            I have a reset code
+          </button>
+          </>
+          )}
         </div>
       </form>
     </div>
@@ -1,7 +1,7 @@
 import NavbarComponent from "../components/NavbarComponent";
 import "../css/ProfilePage.css";
 import { useState, useEffect } from "react";
-import { invoke } from "@tauri-apps/api";
+import { clearSessionToken, invokeWithSession } from "../session";
 import { Link as RouterLink, useNavigate } from "react-router-dom";
 
 export default function ProfilePage() {
@@ -10,20 +10,32 @@ export default function ProfilePage() {
   const [newPassword, setNewPassword] = useState("");
   const [confirmPassword, setConfirmPassword] = useState("");
   const [message, setMessage] = useState("");
+  const [enrollment, setEnrollment] = useState<{ secret: string; otpauth_uri: string } | null>(null);
+  const [twoFactorCode, setTwoFactorCode] = useState("");
+  const [recoveryCodes, setRecoveryCodes] = useState<string[]>([]);
+  const [twoFactorMessage, setTwoFactorMessage] = useState("");
   const navigate = useNavigate();
 
   useEffect(() => {
     const fetchUser = async () => {
-      const user = await invoke("get_current_user");
+      const user = await invokeWithSession("get_current_user");
       console.log("Fetched user:", user); // Log the user object
       setUser(user as User);
+      if ((user as User)?.must_change_password) {
+        setMessage("Please change your default password before continuing.");
+      }
+      if ((user as User)?.must_enroll_two_factor) {
+        setTwoFactorMessage("Your role requires two-factor authentication. Set it up before continuing.");
+      }
     };
 
     fetchUser();
   }, []);
 
   const handleLogout = async () => {
-    await invoke("logout");
+    // An expired session is already gone, so leave either way
+    await invokeWithSession("logout").catch((error) => console.error("Failed to log out:", error));
+    clearSessionToken();
     navigate("/");
   };
 
@@ -39,11 +51,18 @@ export default function ProfilePage() {
     }
 
     try {
-      const response = await invoke("change_password", {
+      const response = await invokeWithSession("change_password", {
         currentPassword,
         newPassword,
       });
       if (response === "Success") {
+        if (user?.must_change_password && !user.must_enroll_two_factor) {
+          navigate(user.login_type === "nim" ? "/home" : "/dashboard", { replace: true });
+          return;
+        }
+        if (user?.must_change_password) {
+          setUser({ ...user, must_change_password: false });
+        }
         setMessage("Password changed successfully.");
         setCurrentPassword("");
         setNewPassword("");
@@ -51,8 +70,34 @@ export default function ProfilePage() {
       } else {
         setMessage("Password change failed");
       }
-    } catch (error) {
-      setMessage("An error occurred while changing the password.");
+    } catch (error: any) {
+      setMessage(error?.message ?? "An error occurred while changing the password.");
+    }
+  };
+
+  const handleBeginTwoFactor = async () => {
+    try {
+      setEnrollment(await invokeWithSession("begin_two_factor_enrollment"));
+      setRecoveryCodes([]);
+      setTwoFactorMessage("Add the key below to your authenticator app, then enter the code it shows.");
+    } catch (error: any) {
+      setTwoFactorMessage(error?.message ?? "Failed to start two-factor setup.");
+    }
+  };
+
+  const handleConfirmTwoFactor = async (e: React.FormEvent) => {
+    e.preventDefault();
+    try {
+      const codes = await invokeWithSession<string[]>("confirm_two_factor_enrollment", { code: twoFactorCode });
+      setRecoveryCodes(codes);
+      setEnrollment(null);
+      setTwoFactorCode("");
+      setTwoFactorMessage("Two-factor authentication is on. Store these recovery codes somewhere safe; each works once.");
+      if (user) {
+        setUser({ ...user, must_enroll_two_factor: false });
+      }
+    } catch (error: any) {
+      setTwoFactorMessage(error?.message ?? "Failed to confirm two-factor setup.");
     }
   };
 
@@ -257,6 +302,62 @@ export default function ProfilePage() {
               </div>
             </div>
             {/* End Change Password Container */}
+            {/* Two-Factor Container */}
+            <div className="col mb-3">
+              <div className="card">
+                <div className="card-body">
+                  <div className="mb-2">
+                    <h4 className="pt-sm-2 pb-1 mb-0 text-nowrap center">
+                      Two-Factor Authentication
+                    </h4>
+                  </div>
+                  {twoFactorMessage && (
+                    <div className="alert alert-info">{twoFactorMessage}</div>
+                  )}
+                  {enrollment ? (
+                    <form onSubmit={handleConfirmTwoFactor}>
+                      <div className="form-group mb-3">
+                        <label>Setup Key</label>
+                        <input className="form-control" type="text" value={enrollment.secret} readOnly />
+                      </div>
+                      <div className="form-group mb-3">
+                        <label>Setup Link</label>
+                        <input className="form-control" type="text" value={enrollment.otpauth_uri} readOnly />
+                      </div>
+                      <div className="form-group mb-3">
+                        <label>Code</label>
+                        <input
+                          className="form-control"
+                          type="text"
+                          autoComplete="one-time-code"
+                          placeholder="123456"
+                          value={twoFactorCode}
+                          onChange={(e) => setTwoFactorCode(e.target.value)}
+                        />
+                      </div>
+                      <div className="d-flex justify-content-end">
+                        <button className="btn btn-primary" type="submit">
+                          Confirm
+                        </button>
+                      </div>
+                    </form>
+                  ) : recoveryCodes.length > 0 ? (
+                    <ul className="list-unstyled font-monospace">
+                      {recoveryCodes.map((code) => (
+                        <li key={code}>{code}</li>
+                      ))}
+                    </ul>
+                  ) : (
+                    <div className="d-flex justify-content-end">
+                      <button className="btn btn-primary" onClick={handleBeginTwoFactor}>
+                        Set Up Two-Factor
+                      </button>
+                    </div>
+                  )}
+                </div>
+              </div>
+            </div>
+            {/* End Two-Factor Container */}
           </div>
         </div>
       </div>
@@ -1,6 +1,6 @@
 // Frontend code
 import { useEffect, useState } from "react";
-import { invoke } from "@tauri-apps/api";
+import { invokeWithSession } from "../session";
 import NavbarComponent from "../components/NavbarComponent";
 
 export default function RoomManagementPage() {
@@ -11,11 +11,11 @@ export default function RoomManagementPage() {
   const [selectedDate, setSelectedDate] = useState<Date | null>(null); // Changed type to Date
 
   useEffect(() => {
-    invoke("get_all_room").then((rooms) => {
-      setRooms(rooms as Room[]);
+    invokeWithSession<Dataset<Room>>("get_all_room").then((rooms) => {
+      setRooms(rooms.rows);
     });
 
-    invoke("get_all_shifts").then((shifts) => {
+    invokeWithSession("get_all_shifts").then((shifts) => {
       console.log("Fetched shifts:", shifts); // Debugging line
       setShifts(shifts as Shift[]);
     });
@@ -23,7 +23,7 @@ export default function RoomManagementPage() {
 
   useEffect(() => {
     if (selectedDate) {
-      invoke("get_scheduled_rooms", {
+      invokeWithSession("get_scheduled_rooms", {
         selectedDate: selectedDate.toISOString().split("T")[0],
       }) // Pass selectedDate with the correct key
         .then((scheduledRooms) => {
@@ -1,5 +1,5 @@
 import { useEffect, useState } from "react";
-import { invoke } from "@tauri-apps/api";
+import { invokeWithSession } from "../session";
 import NavbarComponent from "../components/NavbarComponent";
 import { Alert } from 'react-bootstrap'; // Import Bootstrap's Alert component
 
@@ -22,24 +22,24 @@ export default function StudentExamSchedulerPage() {
   const [submissionDetails, setSubmissionDetails] = useState<string | null>(null);
 
   useEffect(() => {
-    invoke("get_all_subject").then((result) => {
-      setSubjects(result as Subject[]);
+    invokeWithSession<Dataset<Subject>>("get_all_subject").then((result) => {
+      setSubjects(result.rows);
     });
 
-    invoke("get_all_room").then((result) => {
-      setRooms(result as Room[]);
+    invokeWithSession<Dataset<Room>>("get_all_room").then((result) => {
+      setRooms(result.rows);
     });
   }, []);
 
   useEffect(() => {
-    invoke("get_all_shifts").then((result) => {
+    invokeWithSession("get_all_shifts").then((result) => {
       setShifts(result as Shift[]);
     });
   }, []);
 
   useEffect(() => {
     if (selectedSubject) {
-      invoke("get_enrollments_by_subject_code", { subjectCode: selectedSubject }).then((result) => {
+      invokeWithSession("get_enrollments_by_subject_code", { subjectCode: selectedSubject }).then((result) => {
         setClasses(result as Enrollment[]);
       });
     }
@@ -117,7 +117,7 @@ export default function StudentExamSchedulerPage() {
       roomNumber: examRoom,
     };
   
-    invoke("allocate_exam", examData)
+    invokeWithSession("allocate_exam", examData)
       .then((response) => {
         const res = response as any;
         console.log("Exam allocated successfully", res);
@@ -138,7 +138,9 @@ export default function StudentExamSchedulerPage() {
       })
       .catch((error) => {
         console.error("Failed to allocate exam", error);
-        setAlertMessage("Failed to allocate exam.");
+        const clashes = (error?.details?.clashes ?? []) as { nim: string; subject_code: string }[];
+        const clashList = clashes.map((clash) => `${clash.nim} (${clash.subject_code})`).join(", ");
+        setAlertMessage(clashList ? `${error.message}: ${clashList}` : error?.message ?? "Failed to allocate exam.");
         setAlertVariant("danger");
       });
   };
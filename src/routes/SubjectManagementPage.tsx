@@ -1,5 +1,5 @@
 import { useEffect, useState } from "react";
-import { invoke } from "@tauri-apps/api";
+import { invokeWithSession } from "../session";
 import NavbarComponent from "../components/NavbarComponent";
 
 export default function SubjectManagementPage() {
@@ -7,9 +7,8 @@ export default function SubjectManagementPage() {
   const [searchQuery, setSearchQuery] = useState<string>("");
 
   useEffect(() => {
-    invoke("get_all_subject", {}).then((rooms) => {
-      console.log(rooms);
-      setSubjects(rooms as Subject[]);
+    invokeWithSession<Dataset<Subject>>("get_all_subject", {}).then((subjects) => {
+      setSubjects(subjects.rows);
     });
   }, []);
 
@@ -1,23 +1,82 @@
 import { useEffect, useState } from "react";
-import { invoke } from "@tauri-apps/api";
+import { invokeWithSession } from "../session";
 import NavbarComponent from "../components/NavbarComponent";
+import { useNavigate } from "react-router-dom";
 
 export default function UserManagementPage() {
   const [users, setUsers] = useState<User[]>([]);
+  const [freshness, setFreshness] = useState<Dataset<User> | null>(null);
   const [searchQuery, setSearchQuery] = useState<string>("");
   const [roleFilter, setRoleFilter] = useState<string>("");
   const [editingUser, setEditingUser] = useState<User | null>(null);
   const [newRole, setNewRole] = useState<string>("");
   const [alertMessage, setAlertMessage] = useState<string>("");
+  const navigate = useNavigate();
+  const [suspended, setSuspended] = useState<Record<string, { reason: string | null; suspended_until: number | null }>>({});
+  const [passwordReset, setPasswordReset] = useState<{ user: User; code: string; expires_at: number } | null>(null);
 
   useEffect(() => {
-    const fetchUsers = async () => {
-      const fetchedUsers = await invoke("get_all_users", {});
-      setUsers(fetchedUsers as User[]);
-    };
-    fetchUsers();
+    fetchUsers(false);
+    fetchSuspended();
   }, []);
 
+  const fetchUsers = async (forceRemote: boolean) => {
+    try {
+      const fetchedUsers = await invokeWithSession<Dataset<User>>("get_all_users", { forceRemote });
+      setUsers(fetchedUsers.rows);
+      setFreshness(fetchedUsers);
+    } catch (error: any) {
+      alert(error?.message ?? "Failed to load users.");
+    }
+  };
+
+  const describeFreshness = (dataset: Dataset<User>) => {
+    if (dataset.source === "remote") {
+      return "Fetched from the server just now";
+    }
+    if (dataset.age_secs === null) {
+      return "Not synced yet";
+    }
+    return `Last synced ${Math.floor(dataset.age_secs / 60)} minute(s) ago`;
+  };
+
+  const fetchSuspended = async () => {
+    try {
+      const accounts = await invokeWithSession<{ bn_number: string; reason: string | null; suspended_until: number | null }[]>("get_suspended_accounts");
+      setSuspended(Object.fromEntries(accounts.map(({ bn_number, ...suspension }) => [bn_number, suspension])));
+    } catch (error) {
+      console.error("Failed to fetch suspended accounts:", error);
+    }
+  };
+
+  const handleSuspend = async (user: User) => {
+    const reason = window.prompt(`Why is ${user.name} being suspended?`);
+    if (!reason) {
+      return;
+    }
+    const days = window.prompt("Suspend for how many days? Leave empty to suspend until reinstated.");
+    const suspendedUntil = days ? Math.floor(Date.now() / 1000) + Number(days) * 24 * 60 * 60 : null;
+    try {
+      await invokeWithSession("suspend_account", { bnNumber: user.bn_number, reason, suspendedUntil });
+      await fetchSuspended();
+    } catch (error: any) {
+      alert(error?.message ?? "Failed to suspend the account.");
+    }
+  };
+
+  const handleReinstate = async (user: User) => {
+    const reason = window.prompt(`Why is ${user.name} being reinstated?`);
+    if (!reason) {
+      return;
+    }
+    try {
+      await invokeWithSession("reinstate_account", { bnNumber: user.bn_number, reason });
+      await fetchSuspended();
+    } catch (error: any) {
+      alert(error?.message ?? "Failed to reinstate the account.");
+    }
+  };
+
   const filteredUsers = users.filter(user =>
     (user.name?.toLowerCase().includes(searchQuery.toLowerCase()) ||
     user.initial?.toLowerCase().includes(searchQuery.toLowerCase()) ||
@@ -39,7 +98,7 @@ export default function UserManagementPage() {
 
     if (editingUser && newRole) {
       try {
-        await invoke("update_user_role", { bnNumber: editingUser.bn_number, newRole });
+        await invokeWithSession("update_user_role", { bnNumber: editingUser.bn_number, newRole });
         setUsers(users.map(user => user.bn_number === editingUser.bn_number ? { ...user, role: newRole } : user));
         setEditingUser(null);
       } catch (error) {
@@ -48,6 +107,27 @@ export default function UserManagementPage() {
     }
   };
 
+  const handleViewAs = async (user: User) => {
+    try {
+      const viewed = await invokeWithSession<User>("start_impersonation", { bnNumber: user.bn_number });
+      navigate(viewed.login_type === "nim" ? "/home" : "/dashboard");
+    } catch (error: any) {
+      alert(error?.message ?? "Failed to view as this user.");
+    }
+  };
+
+  const handleIssueReset = async (user: User) => {
+    if (!window.confirm(`Issue a password reset code for ${user.name}?`)) {
+      return;
+    }
+    try {
+      const reset = await invokeWithSession<{ code: string; expires_at: number }>("issue_password_reset", { bnNumber: user.bn_number });
+      setPasswordReset({ user, code: reset.code, expires_at: reset.expires_at });
+    } catch (error: any) {
+      alert(error?.message ?? "Failed to issue a reset code.");
+    }
+  };
+
   return (
     <div className="w-screen h-screen">
       <NavbarComponent />
@@ -63,6 +143,13 @@ export default function UserManagementPage() {
         />
       </div>
 
+      <div className="ml-5 mb-2 mr-5 d-flex align-items-center">
+        <small className="text-muted mr-3">{freshness ? describeFreshness(freshness) : "Loading..."}</small>
+        <button className="btn btn-sm btn-outline-secondary" onClick={() => fetchUsers(true)}>
+          Refresh from server
+        </button>
+      </div>
+
       <div className="ml-5 mb-3">
         <select 
           value={roleFilter}
@@ -87,6 +174,7 @@ export default function UserManagementPage() {
               <th scope="col">Major</th>
               <th scope="col">Role</th>
               <th scope="col">Initial</th>
+              <th scope="col">Status</th>
               <th scope="col">Actions</th>
             </tr>
           </thead>
@@ -99,6 +187,13 @@ export default function UserManagementPage() {
                 <td>{user.major}</td>
                 <td>{user.role}</td>
                 <td>{user.initial || "-"}</td>
+                <td title={suspended[user.bn_number as string]?.reason ?? undefined}>
+                  {suspended[user.bn_number as string]
+                    ? `Suspended${suspended[user.bn_number as string].suspended_until
+                        ? ` until ${new Date(suspended[user.bn_number as string].suspended_until! * 1000).toLocaleDateString()}`
+                        : ""}`
+                    : "Active"}
+                </td>
                 <td>
                   <button 
                     className="btn btn-primary" 
@@ -106,6 +201,27 @@ export default function UserManagementPage() {
                   >
                     Edit
                   </button>
+                  <button
+                    className="btn btn-info ml-2"
+                    onClick={() => handleViewAs(user)}
+                  >
+                    View As
+                  </button>
+                  <button
+                    className="btn btn-secondary ml-2"
+                    onClick={() => handleIssueReset(user)}
+                  >
+                    Reset Password
+                  </button>
+                  {suspended[user.bn_number as string] ? (
+                    <button className="btn btn-success ml-2" onClick={() => handleReinstate(user)}>
+                      Reinstate
+                    </button>
+                  ) : (
+                    <button className="btn btn-danger ml-2" onClick={() => handleSuspend(user)}>
+                      Suspend
+                    </button>
+                  )}
                 </td>
               </tr>
             ))}
@@ -157,6 +273,33 @@ export default function UserManagementPage() {
           </div>
         </div>
       )}
+
+      {passwordReset && (
+        <div className="modal fade show" style={{ display: 'block' }}>
+          <div className="modal-dialog">
+            <div className="modal-content">
+              <div className="modal-header">
+                <h5 className="modal-title">Password Reset Code</h5>
+                <button type="button" className="close" onClick={() => setPasswordReset(null)}>
+                  <span>&times;</span>
+                </button>
+              </div>
+              <div className="modal-body">
+                <p>
+                  Give this code to {passwordReset.user.name}. It works once, until{" "}
+                  {new Date(passwordReset.expires_at * 1000).toLocaleString()}.
+                </p>
+                <input className="form-control font-monospace" type="text" value={passwordReset.code} readOnly />
+              </div>
+              <div className="modal-footer">
+                <button type="button" className="btn btn-primary" onClick={() => setPasswordReset(null)}>
+                  Done
+                </button>
+              </div>
+            </div>
+          </div>
+        </div>
+      )}
     </div>
   );
 }
\ No newline at end of file
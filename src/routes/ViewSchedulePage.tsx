@@ -1,5 +1,5 @@
 import { useEffect, useState } from "react";
-import { invoke } from "@tauri-apps/api";
+import { invokeWithSession } from "../session";
 import NavbarComponent from "../components/NavbarComponent";
 
 export default function ViewSchedulePage() {
@@ -11,8 +11,8 @@ export default function ViewSchedulePage() {
 
   useEffect(() => {
     const fetchUsers = async () => {
-      const fetchedUsers = await invoke("get_all_users", {});
-      setUsers(fetchedUsers as User[]);
+      const fetchedUsers = await invokeWithSession<Dataset<User>>("get_all_users", {});
+      setUsers(fetchedUsers.rows);
     };
     fetchUsers();
   }, []);
@@ -1,6 +1,6 @@
 import NavbarComponent from "../components/NavbarComponent";
 import { useEffect, useState } from "react";
-import { invoke } from "@tauri-apps/api";
+import { invokeWithSession } from "../session";
 import {
   Specification,
   DateSpecification,
@@ -22,12 +22,12 @@ export default function ViewTransactionPage() {
   });
 
   useEffect(() => {
-    invoke("view_transaction").then((result) => {
+    invokeWithSession("view_transaction").then((result) => {
       setTransactions(result as ViewTransaction[]);
     });
 
-    invoke("get_all_subject").then((result) => {
-      const subjectsData = result as Subject[];
+    invokeWithSession<Dataset<Subject>>("get_all_subject").then((result) => {
+      const subjectsData = result.rows;
       setSubjects(subjectsData);
 
       const subjectMapping: { [key: string]: string } = {};
@@ -37,7 +37,7 @@ export default function ViewTransactionPage() {
       setSubjectCodeToNameMap(subjectMapping);
     });
 
-    invoke("get_all_shifts").then((result) => {
+    invokeWithSession("get_all_shifts").then((result) => {
       const shiftsData = result as Shift[];
       const shiftMapping: { [key: string]: string } = {};
       shiftsData.forEach(shift => {
@@ -0,0 +1,29 @@
+import { invoke } from "@tauri-apps/api";
+import { InvokeArgs } from "@tauri-apps/api/tauri";
+import { listen, UnlistenFn } from "@tauri-apps/api/event";
+
+const TOKEN_KEY = "sessionToken";
+
+// sessionStorage is per window, so every window keeps its own login.
+export function saveSessionToken(token: string) {
+  sessionStorage.setItem(TOKEN_KEY, token);
+}
+
+export function clearSessionToken() {
+  sessionStorage.removeItem(TOKEN_KEY);
+}
+
+// Calls a command with this window's session token added to its arguments.
+export function invokeWithSession<T>(command: string, args: InvokeArgs = {}): Promise<T> {
+  return invoke<T>(command, { ...args, token: sessionStorage.getItem(TOKEN_KEY) });
+}
+
+// Runs `onExpired` when the backend closes this window's session for being idle.
+export function listenForSessionExpiry(onExpired: () => void): Promise<UnlistenFn> {
+  return listen<{ token: string }>("session://expired", (event) => {
+    if (event.payload.token === sessionStorage.getItem(TOKEN_KEY)) {
+      clearSessionToken();
+      onExpired();
+    }
+  });
+}
@@ -0,0 +1,18 @@
+import { listen, UnlistenFn } from "@tauri-apps/api/event";
+
+export type SyncProgress = { run_id: number; stage: string; message: string };
+export type SyncTableReport = { table: string; inserted: number; updated: number; deleted: number; skipped: number; kept_local: number };
+export type SyncDone = {
+  report: { run_id: number; tables: SyncTableReport[] } | null;
+  error: { code: string; message: string } | null;
+};
+
+// Follows background and manual syncs, which run in the backend for every window.
+export async function listenForSync(onProgress: (progress: SyncProgress) => void, onDone: (done: SyncDone) => void): Promise<UnlistenFn> {
+  const stopProgress = await listen<SyncProgress>("sync://progress", (event) => onProgress(event.payload));
+  const stopDone = await listen<SyncDone>("sync://done", (event) => onDone(event.payload));
+  return () => {
+    stopProgress();
+    stopDone();
+  };
+}